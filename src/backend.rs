@@ -0,0 +1,76 @@
+mod chatgpt_rs;
+mod mock;
+mod openai;
+
+use anyhow::Result;
+use chatgpt::types::{ChatMessage, Role};
+use futures::{future::BoxFuture, stream::BoxStream};
+
+use crate::config::{BackendKind, ModelSettings, Profile};
+
+pub use self::{chatgpt_rs::ChatGptRsBackend, mock::MockBackend, openai::OpenAiBackend};
+
+/// A single event of a streamed response, independent of the backend that produced it
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResponseChunk {
+    /// Marks the beginning of the answer, with no actual content yet
+    BeginResponse { role: Role },
+    /// A piece of the answer
+    Content { delta: String },
+    /// The answer is complete, `finish_reason` is e.g. "stop" or "length" if the backend reports it
+    CloseResponse { finish_reason: Option<String> },
+    /// How many tokens the request used, only sent if the backend reports it
+    Usage { prompt_tokens: u32, completion_tokens: u32 },
+    /// Marks the end of the stream
+    Done,
+}
+
+/// Why a request failed, `status` is the HTTP status code if the server answered with an error
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestError {
+    pub status: Option<u16>,
+    pub message: String,
+}
+
+impl RequestError {
+    /// Keeps the status code if `err` is a [`RequestError`], otherwise only the message is used
+    pub fn from_error(err: anyhow::Error) -> Self {
+        err.downcast().unwrap_or_else(|err| RequestError { status: None, message: format!("{err:#}") })
+    }
+}
+
+impl std::fmt::Display for RequestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.status {
+            Some(status) => {
+                let reason = reqwest::StatusCode::from_u16(status).ok().and_then(|s| s.canonical_reason());
+                write!(f, "{status} {}: {}", reason.unwrap_or(""), self.message)
+            }
+            None => f.write_str(&self.message),
+        }
+    }
+}
+
+impl std::error::Error for RequestError {}
+
+pub type ResponseStream = BoxStream<'static, Result<ResponseChunk>>;
+
+/// Something that can answer chats, e.g. the OpenAI API or a server speaking the same protocol
+pub trait ChatBackend: Send + Sync {
+    /// Sends the whole history and streams back the answer,
+    /// the stream always begins with [`ResponseChunk::BeginResponse`] and ends with [`ResponseChunk::Done`] unless it fails
+    fn send_history_streaming(
+        &self,
+        messages: Vec<ChatMessage>,
+        model: &ModelSettings,
+    ) -> BoxFuture<'static, Result<ResponseStream>>;
+}
+
+/// Creates the backend described by `profile`
+pub fn from_profile(profile: &Profile, api_key: Option<String>) -> Result<Box<dyn ChatBackend>> {
+    Ok(match profile.backend {
+        BackendKind::OpenAi => Box::new(OpenAiBackend::new(profile.base_url(), api_key, profile.stream_usage())?),
+        BackendKind::ChatGptRs => Box::new(ChatGptRsBackend::new(profile.base_url(), api_key.unwrap_or_default())?),
+        BackendKind::Mock => Box::new(MockBackend::new(profile.mock.clone())),
+    })
+}
@@ -0,0 +1,50 @@
+use anyhow::Result;
+use chatgpt::{prelude::ChatGPT, types::ChatMessage};
+use futures::{future::BoxFuture, FutureExt, StreamExt};
+
+use super::{ChatBackend, ResponseChunk, ResponseStream};
+use crate::config::ModelSettings;
+
+/// The backend that uses the `chatgpt_rs` crate, it doesn't support `max_tokens`
+pub struct ChatGptRsBackend {
+    client: ChatGPT,
+}
+
+impl ChatGptRsBackend {
+    pub fn new(base_url: &str, api_key: String) -> Result<Self> {
+        let mut client = ChatGPT::new(api_key)?;
+        client.config.api_url = format!("{}/chat/completions", base_url.trim_end_matches('/')).parse()?;
+        Ok(ChatGptRsBackend { client })
+    }
+}
+
+impl ChatBackend for ChatGptRsBackend {
+    fn send_history_streaming(
+        &self,
+        messages: Vec<ChatMessage>,
+        model: &ModelSettings,
+    ) -> BoxFuture<'static, Result<ResponseStream>> {
+        // the reqwest client is shared between clones, so this is cheap
+        let mut client = self.client.clone();
+        client.config =
+            chatgpt::prelude::ModelConfiguration { api_url: client.config.api_url, ..model.model_configuration() };
+        async move {
+            let stream = client.send_history_streaming(&messages).await?;
+            Ok(stream
+                .map(|chunk| {
+                    Ok(match chunk {
+                        chatgpt::types::ResponseChunk::BeginResponse { role, .. } => {
+                            ResponseChunk::BeginResponse { role }
+                        }
+                        chatgpt::types::ResponseChunk::Content { delta, .. } => ResponseChunk::Content { delta },
+                        chatgpt::types::ResponseChunk::CloseResponse { .. } => {
+                            ResponseChunk::CloseResponse { finish_reason: None }
+                        }
+                        chatgpt::types::ResponseChunk::Done => ResponseChunk::Done,
+                    })
+                })
+                .boxed())
+        }
+        .boxed()
+    }
+}
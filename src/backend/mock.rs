@@ -0,0 +1,76 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use chatgpt::types::{ChatMessage, Role};
+use futures::{future::BoxFuture, FutureExt, StreamExt};
+
+use super::{ChatBackend, ResponseChunk, ResponseStream};
+use crate::config::{MockSettings, ModelSettings};
+
+/// A deterministic fake backend, that doesn't need the network.
+///
+/// It answers with one of the configured canned responses (chosen by the number of user messages in the history),
+/// or echoes the last user message if there are none
+pub struct MockBackend {
+    settings: MockSettings,
+}
+
+impl MockBackend {
+    pub fn new(settings: MockSettings) -> Self {
+        MockBackend { settings }
+    }
+
+    fn response(&self, messages: &[ChatMessage]) -> String {
+        let user_messages = messages.iter().filter(|m| m.role == Role::User).count();
+        match messages.last() {
+            _ if !self.settings.responses.is_empty() => {
+                let idx = user_messages.saturating_sub(1) % self.settings.responses.len();
+                self.settings.responses[idx].clone()
+            }
+            Some(ChatMessage { role: Role::User, content }) => format!("You said: {content}"),
+            // e.g. title requests, which only consist of a system message
+            _ => "Mock response".into(),
+        }
+    }
+}
+
+impl ChatBackend for MockBackend {
+    fn send_history_streaming(
+        &self,
+        messages: Vec<ChatMessage>,
+        _model: &ModelSettings,
+    ) -> BoxFuture<'static, Result<ResponseStream>> {
+        let response = self.response(&messages);
+        // words instead of tokens, but it's close enough for testing
+        let prompt_tokens = messages.iter().map(|m| m.content.split_whitespace().count() as u32).sum();
+        let completion_tokens = response.split_whitespace().count() as u32;
+        let first_chunk_delay = Duration::from_millis(self.settings.first_chunk_delay_ms);
+        let chunk_delay = Duration::from_millis(self.settings.chunk_delay_ms);
+
+        // split after whitespace, so that the deltas concatenated give the exact response again
+        let deltas =
+            response.split_inclusive(char::is_whitespace).map(|delta| ResponseChunk::Content { delta: delta.into() });
+        let chunks: Vec<_> = std::iter::once(ResponseChunk::BeginResponse { role: Role::Assistant })
+            .chain(deltas)
+            .chain([
+                ResponseChunk::CloseResponse { finish_reason: Some("stop".into()) },
+                ResponseChunk::Usage { prompt_tokens, completion_tokens },
+                ResponseChunk::Done,
+            ])
+            .collect();
+
+        async move {
+            tokio::time::sleep(first_chunk_delay).await;
+            Ok(futures::stream::iter(chunks)
+                .enumerate()
+                .then(move |(i, chunk)| async move {
+                    if i > 0 {
+                        tokio::time::sleep(chunk_delay).await;
+                    }
+                    Ok(chunk)
+                })
+                .boxed())
+        }
+        .boxed()
+    }
+}
@@ -0,0 +1,314 @@
+use anyhow::{anyhow, Context, Result};
+use chatgpt::types::{ChatMessage, Role};
+use eventsource_stream::Eventsource;
+use futures::{future::BoxFuture, FutureExt, StreamExt};
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION};
+use serde::{Deserialize, Serialize};
+
+use super::{ChatBackend, RequestError, ResponseChunk, ResponseStream};
+use crate::config::ModelSettings;
+
+/// Talks to anything that implements the OpenAI `/v1/chat/completions` streaming protocol,
+/// e.g. OpenAI itself, llama.cpp server, vLLM or LocalAI
+pub struct OpenAiBackend {
+    client: reqwest::Client,
+    completions_url: String,
+    stream_usage: bool,
+}
+
+impl OpenAiBackend {
+    /// `base_url` is the url that the OpenAI paths are appended to, e.g. `https://api.openai.com/v1`,
+    /// with `stream_usage` the token usage is requested at the end of the stream
+    pub fn new(base_url: &str, api_key: Option<String>, stream_usage: bool) -> Result<Self> {
+        let mut headers = HeaderMap::new();
+        if let Some(api_key) = api_key {
+            headers.insert(AUTHORIZATION, HeaderValue::from_str(&format!("Bearer {api_key}"))?);
+        }
+        let client = reqwest::ClientBuilder::new().default_headers(headers).build()?;
+        let completions_url = format!("{}/chat/completions", base_url.trim_end_matches('/'));
+        Ok(OpenAiBackend { client, completions_url, stream_usage })
+    }
+}
+
+#[derive(Serialize)]
+struct CompletionRequest<'a> {
+    model: &'a str,
+    messages: &'a [ChatMessage],
+    stream: bool,
+    temperature: f32,
+    top_p: f32,
+    presence_penalty: f32,
+    frequency_penalty: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream_options: Option<StreamOptions>,
+}
+
+#[derive(Serialize)]
+struct StreamOptions {
+    include_usage: bool,
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: ErrorDetails,
+}
+
+#[derive(Deserialize)]
+struct ErrorDetails {
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct InboundChunk {
+    #[serde(default)]
+    choices: Vec<InboundChoice>,
+    /// Only set in the last chunk, if the usage was requested
+    usage: Option<Usage>,
+}
+
+#[derive(Deserialize)]
+struct Usage {
+    prompt_tokens: u32,
+    completion_tokens: u32,
+}
+
+#[derive(Deserialize)]
+struct InboundChoice {
+    #[serde(default)]
+    delta: InboundDelta,
+    finish_reason: Option<String>,
+}
+
+// some servers send the role and the first content in the same delta, so both are optional
+#[derive(Deserialize, Default)]
+struct InboundDelta {
+    role: Option<Role>,
+    content: Option<String>,
+}
+
+#[derive(Default)]
+struct StreamState {
+    began: bool,
+    finished: bool,
+}
+
+impl StreamState {
+    /// Converts a single server sent event to response chunks
+    fn parse_event(&mut self, data: &str) -> Result<Vec<ResponseChunk>> {
+        if data == "[DONE]" {
+            return Ok(vec![]);
+        }
+        let chunk: InboundChunk =
+            serde_json::from_str(data).with_context(|| format!("Invalid streaming response payload: {data}"))?;
+        let mut chunks = Vec::new();
+        // only one reply is requested, so the other choices (if there are any) are ignored
+        if let Some(choice) = chunk.choices.into_iter().next() {
+            if !self.began {
+                self.began = true;
+                chunks.push(ResponseChunk::BeginResponse { role: choice.delta.role.unwrap_or(Role::Assistant) });
+            }
+            if let Some(delta) = choice.delta.content.filter(|c| !c.is_empty()) {
+                chunks.push(ResponseChunk::Content { delta });
+            }
+            if let Some(finish_reason) = choice.finish_reason {
+                chunks.push(ResponseChunk::CloseResponse { finish_reason: Some(finish_reason) });
+            }
+        }
+        if let Some(usage) = chunk.usage.filter(|_| self.began) {
+            chunks.push(ResponseChunk::Usage {
+                prompt_tokens: usage.prompt_tokens,
+                completion_tokens: usage.completion_tokens,
+            });
+        }
+        Ok(chunks)
+    }
+}
+
+impl ChatBackend for OpenAiBackend {
+    fn send_history_streaming(
+        &self,
+        messages: Vec<ChatMessage>,
+        model: &ModelSettings,
+    ) -> BoxFuture<'static, Result<ResponseStream>> {
+        let request = self.client.post(&self.completions_url).json(&CompletionRequest {
+            model: &model.engine,
+            messages: &messages,
+            stream: true,
+            temperature: model.temperature,
+            top_p: model.top_p,
+            presence_penalty: model.presence_penalty,
+            frequency_penalty: model.frequency_penalty,
+            max_tokens: model.max_tokens,
+            stream_options: self.stream_usage.then_some(StreamOptions { include_usage: true }),
+        });
+        async move {
+            let response = request.send().await?;
+            let status = response.status();
+            if !status.is_success() {
+                let body = response.text().await.unwrap_or_default();
+                let message = serde_json::from_str::<ErrorResponse>(&body).map(|e| e.error.message).unwrap_or(body);
+                return Err(RequestError { status: Some(status.as_u16()), message }.into());
+            }
+            let stream = response
+                .bytes_stream()
+                .eventsource()
+                .map(Some)
+                // marks the end of the event stream, so that `Done` can be sent even if the server doesn't send [DONE]
+                .chain(futures::stream::once(async { None }))
+                .scan(StreamState::default(), |state, event| {
+                    if state.finished {
+                        return futures::future::ready(None);
+                    }
+                    let chunks = match event {
+                        Some(Ok(event)) => state.parse_event(&event.data),
+                        Some(Err(err)) => Err(anyhow!("Stream closed abruptly: {err}")),
+                        None if !state.began => Err(anyhow!("The stream ended without a response")),
+                        None => Ok(vec![ResponseChunk::Done]),
+                    };
+                    // nothing is sent anymore after the stream failed
+                    state.finished = chunks.is_err();
+                    futures::future::ready(Some(futures::stream::iter(match chunks {
+                        Ok(chunks) => chunks.into_iter().map(Ok).collect(),
+                        Err(err) => vec![Err(err)],
+                    })))
+                })
+                .flatten();
+            Ok(stream.boxed())
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chatgpt_tui::mock_server;
+
+    fn parse(events: &[&str]) -> Vec<ResponseChunk> {
+        let mut state = StreamState::default();
+        events.iter().flat_map(|event| state.parse_event(event).unwrap()).collect()
+    }
+
+    fn content(delta: &str) -> ResponseChunk {
+        ResponseChunk::Content { delta: delta.into() }
+    }
+
+    #[test]
+    fn parse_event_begins_the_response_once() {
+        let chunks = parse(&[
+            r#"{"choices": [{"delta": {"role": "assistant"}, "finish_reason": null}]}"#,
+            r#"{"choices": [{"delta": {"content": "Hello"}, "finish_reason": null}]}"#,
+            r#"{"choices": [{"delta": {"content": " world"}, "finish_reason": null}]}"#,
+            r#"{"choices": [{"delta": {}, "finish_reason": "stop"}]}"#,
+            "[DONE]",
+        ]);
+        assert_eq!(
+            chunks,
+            [
+                ResponseChunk::BeginResponse { role: Role::Assistant },
+                content("Hello"),
+                content(" world"),
+                ResponseChunk::CloseResponse { finish_reason: Some("stop".into()) },
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_event_accepts_the_role_and_content_in_one_delta() {
+        let chunks = parse(&[r#"{"choices": [{"delta": {"role": "assistant", "content": "Hi"}}]}"#]);
+        assert_eq!(chunks, [ResponseChunk::BeginResponse { role: Role::Assistant }, content("Hi")]);
+    }
+
+    #[test]
+    fn parse_event_begins_without_a_role() {
+        let chunks = parse(&[r#"{"choices": [{"delta": {"content": "Hi"}}]}"#]);
+        assert_eq!(chunks, [ResponseChunk::BeginResponse { role: Role::Assistant }, content("Hi")]);
+    }
+
+    #[test]
+    fn parse_event_reports_the_usage_only_after_the_response_began() {
+        let usage = r#"{"choices": [], "usage": {"prompt_tokens": 12, "completion_tokens": 3}}"#;
+        assert_eq!(parse(&[usage]), []);
+        let chunks = parse(&[r#"{"choices": [{"delta": {"role": "assistant"}}]}"#, usage]);
+        assert_eq!(
+            chunks,
+            [
+                ResponseChunk::BeginResponse { role: Role::Assistant },
+                ResponseChunk::Usage { prompt_tokens: 12, completion_tokens: 3 },
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_event_fails_on_invalid_json() {
+        let err = StreamState::default().parse_event("{\"this is\": not json").unwrap_err();
+        assert!(format!("{err:#}").contains("Invalid streaming response payload"), "{err:#}");
+    }
+
+    fn mock_server() -> OpenAiBackend {
+        let settings = mock_server::Settings { chunk_delay_ms: 0, fail_with: None };
+        let (addr, server) = mock_server::serve(([127, 0, 0, 1], 0).into(), settings).unwrap();
+        tokio::spawn(server);
+        OpenAiBackend::new(&format!("http://{addr}/v1"), Some("key".into()), true).unwrap()
+    }
+
+    /// The chunks of the answer to `content` until the stream ends or fails
+    async fn send(backend: &OpenAiBackend, content: &str) -> Result<(Vec<ResponseChunk>, Option<anyhow::Error>)> {
+        let messages = vec![ChatMessage { role: Role::User, content: content.into() }];
+        let mut stream = backend.send_history_streaming(messages, &ModelSettings::default()).await?;
+        let mut chunks = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            match chunk {
+                Ok(chunk) => chunks.push(chunk),
+                Err(err) => return Ok((chunks, Some(err))),
+            }
+        }
+        Ok((chunks, None))
+    }
+
+    #[tokio::test]
+    async fn streams_the_answer_of_the_mock_server() {
+        let (chunks, err) = send(&mock_server(), "Hi there").await.unwrap();
+        assert!(err.is_none());
+        assert_eq!(
+            chunks,
+            [
+                ResponseChunk::BeginResponse { role: Role::Assistant },
+                content("You "),
+                content("said: "),
+                content("Hi "),
+                content("there"),
+                ResponseChunk::CloseResponse { finish_reason: Some("stop".into()) },
+                ResponseChunk::Usage { prompt_tokens: 2, completion_tokens: 4 },
+                ResponseChunk::Done,
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_the_status_of_an_error_response() {
+        let err = send(&mock_server(), "/error 429").await.unwrap_err();
+        let err = RequestError::from_error(err);
+        assert_eq!(err.status, Some(429));
+        assert_eq!(err.message, "Requested error 429 Too Many Requests");
+    }
+
+    #[tokio::test]
+    async fn fails_on_a_broken_stream_after_the_chunks_so_far() {
+        let (chunks, err) = send(&mock_server(), "/error stream").await.unwrap();
+        assert_eq!(
+            chunks,
+            [
+                ResponseChunk::BeginResponse { role: Role::Assistant },
+                content("You "),
+                content("said: "),
+                content("/error "),
+            ]
+        );
+        let err = RequestError::from_error(err.unwrap());
+        assert_eq!(err.status, None);
+        assert!(err.message.contains("Invalid streaming response payload"), "{}", err.message);
+    }
+}
@@ -0,0 +1,29 @@
+//! A small local stand-in for the OpenAI `/v1/chat/completions` endpoint, for offline use,
+//! [`mock_server`] describes how errors can be provoked.
+//!
+//! Start it with `cargo run --features mock-server --bin mock-server`,
+//! and point a profile with `base_url = "http://127.0.0.1:8080/v1"` at it.
+
+use std::net::SocketAddr;
+
+use anyhow::Result;
+use chatgpt_tui::mock_server;
+use clap::Parser;
+
+#[derive(Debug, Clone, Parser)]
+#[command(about = "Emulates the OpenAI chat completions endpoint")]
+struct Args {
+    /// Address to listen on
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    addr: SocketAddr,
+    #[command(flatten)]
+    settings: mock_server::Settings,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+    let (addr, server) = mock_server::serve(args.addr, args.settings)?;
+    eprintln!("Listening on http://{addr}/v1/chat/completions");
+    Ok(server.await?)
+}
@@ -0,0 +1,430 @@
+use anyhow::{bail, Result};
+use chatgpt::types::{ChatMessage, Role};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{backend::RequestError, config::ChatModelSettings};
+
+/// A message of a chat, together with information that isn't sent to the model
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Message {
+    #[serde(flatten)]
+    pub message: ChatMessage,
+    /// Index of the message that this one follows, only the system prompt at the root of the tree has none
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<usize>,
+    /// The answer was stopped by the user before it was complete
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub stopped: bool,
+    /// The answer was still streamed when it was saved, if that's still the case after loading,
+    /// the app was closed or crashed midway
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub incomplete: bool,
+    /// Unknown for messages that were saved by older versions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<DateTime<Utc>>,
+    /// The model that wrote the answer
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// Why the answer ended as reported by the backend, e.g. "stop" or "length"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finish_reason: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_tokens: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub completion_tokens: Option<u32>,
+    /// Milliseconds from sending the request until the first content of the answer arrived
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time_to_first_token_ms: Option<u64>,
+    /// Milliseconds from sending the request until the answer was complete (or stopped)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+}
+
+impl Message {
+    pub fn new(role: Role, content: String, parent: Option<usize>) -> Self {
+        Message {
+            message: ChatMessage { role, content },
+            parent,
+            stopped: false,
+            incomplete: false,
+            created_at: Some(Utc::now()),
+            model: None,
+            finish_reason: None,
+            prompt_tokens: None,
+            completion_tokens: None,
+            time_to_first_token_ms: None,
+            duration_ms: None,
+        }
+    }
+}
+
+impl std::ops::Deref for Message {
+    type Target = ChatMessage;
+
+    fn deref(&self) -> &ChatMessage {
+        &self.message
+    }
+}
+
+impl std::ops::DerefMut for Message {
+    fn deref_mut(&mut self) -> &mut ChatMessage {
+        &mut self.message
+    }
+}
+
+/// The format of messages before chats were trees, the history was a flat list,
+/// and every message could hold the inactive versions of the conversation after it
+#[derive(Debug, Deserialize)]
+struct FlatMessage {
+    #[serde(flatten)]
+    message: ChatMessage,
+    #[serde(default)]
+    stopped: bool,
+    #[serde(default)]
+    branches: Vec<Vec<FlatMessage>>,
+    #[serde(default)]
+    active_branch: usize,
+}
+
+// TODO support more than one reply count
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Chat {
+    /// Every message of every version of the conversation, they form a tree via [`Message::parent`]
+    #[serde(default)]
+    pub messages: Vec<Message>,
+    /// The last message of the active branch, i.e. the conversation that is shown and sent to the model
+    #[serde(default)]
+    pub head: usize,
+    /// The history in the flat format of older versions, it's moved into `messages` by [`Chat::migrate_to_message_tree`]
+    #[serde(default, rename = "history", skip_serializing)]
+    flat_history: Vec<FlatMessage>,
+    pub title: String,
+    /// The title was set by the user, so it's never replaced by a generated one
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub manual_title: bool,
+    /// User defined tags like "work", the chat list has a section for every tag
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Pinned chats are listed before the others
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pinned: bool,
+    /// Archived chats are only listed in the archive, but they are still found by the search
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub archived: bool,
+    /// Unknown for chats that were created by older versions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<DateTime<Utc>>,
+    /// When the last message was added
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<DateTime<Utc>>,
+    /// Current value of the input box
+    pub input: String,
+    pub input_pos: usize,
+    pub scroll: usize,
+    pub id: Uuid,
+    /// Overrides of the global model settings for this chat
+    #[serde(default)]
+    pub model: ChatModelSettings,
+    /// The last request of this chat failed, it's shown below the messages until the request is retried
+    #[serde(skip)]
+    pub error: Option<RequestError>,
+    /// Index of the message that is currently streamed
+    #[serde(skip)]
+    pub streaming: Option<usize>,
+    /// When the request of the streamed message was sent
+    #[serde(skip)]
+    pub request_sent_at: Option<std::time::Instant>,
+    /// The last request continued the head of the chat instead of streaming a new message
+    #[serde(skip)]
+    pub continuing: bool,
+    /// The title that is currently generated, it replaces the title only when it's complete
+    #[serde(skip)]
+    pub generated_title: Option<String>,
+    /// Index of the message that is highlighted in `UiMode::MessageSelection`
+    #[serde(skip)]
+    pub selected_message: Option<usize>,
+    /// Index of the user message that is edited in the input box, sending it starts a new branch
+    #[serde(skip)]
+    pub editing: Option<usize>,
+    /// A search match whose occurrences are highlighted, the index of the message and the query
+    #[serde(skip)]
+    pub highlight: Option<(usize, String)>,
+    /// The view is scrolled to the highlighted message the next time it's drawn
+    #[serde(skip)]
+    pub scroll_to_highlight: bool,
+}
+
+impl Chat {
+    pub fn new(system_prompt: String) -> Self {
+        let id = Uuid::new_v4();
+        Chat {
+            title: id.to_string(),
+            manual_title: false,
+            tags: Vec::new(),
+            pinned: false,
+            archived: false,
+            created_at: Some(Utc::now()),
+            updated_at: None,
+            id,
+            scroll: 0,
+            input: String::new(),
+            input_pos: 0,
+            messages: vec![Message::new(Role::System, system_prompt, None)],
+            head: 0,
+            flat_history: Vec::new(),
+            model: ChatModelSettings::default(),
+            error: None,
+            streaming: None,
+            request_sent_at: None,
+            selected_message: None,
+            editing: None,
+            highlight: None,
+            scroll_to_highlight: false,
+            continuing: false,
+            generated_title: None,
+        }
+    }
+
+    /// Converts the flat history of older versions into the message tree
+    pub fn migrate_to_message_tree(&mut self) {
+        if self.messages.is_empty() {
+            let history = std::mem::take(&mut self.flat_history);
+            self.head = self.add_flat_history(history, None).unwrap_or_default();
+        }
+    }
+
+    /// Adds `history` as descendants of `parent` and returns the last message of its active branch
+    fn add_flat_history(&mut self, history: Vec<FlatMessage>, parent: Option<usize>) -> Option<usize> {
+        let mut history = history.into_iter();
+        let Some(flat_message) = history.next() else {
+            return parent;
+        };
+        let idx = self.messages.len();
+        let mut message = Message::new(flat_message.message.role, flat_message.message.content, parent);
+        message.stopped = flat_message.stopped;
+        // it's unknown when the message was written
+        message.created_at = None;
+        self.messages.push(message);
+
+        // the versions keep their order, the active one was the rest of the history
+        let mut earlier_branches = flat_message.branches;
+        let later_branches = earlier_branches.split_off(flat_message.active_branch.min(earlier_branches.len()));
+        for branch in earlier_branches {
+            self.add_flat_history(branch, Some(idx));
+        }
+        let head = self.add_flat_history(history.collect(), Some(idx));
+        for branch in later_branches {
+            self.add_flat_history(branch, Some(idx));
+        }
+        head
+    }
+
+    /// Makes sure that the messages form a tree that can be shown, e.g. a chat that was saved without messages
+    /// has no system prompt to start from
+    pub fn check(&self) -> Result<()> {
+        if self.messages.is_empty() {
+            bail!("The chat has no messages");
+        }
+        if self.head >= self.messages.len() {
+            bail!("The last message {} of the chat doesn't exist, it has {} messages", self.head, self.messages.len());
+        }
+        // every message follows an earlier one, except for the system prompt at the root
+        if let Some(idx) = (0..self.messages.len()).find(|&idx| self.messages[idx].parent.map_or(false, |p| p >= idx)) {
+            bail!("The message {idx} doesn't follow an earlier message");
+        }
+        Ok(())
+    }
+
+    /// Indices of the messages of the active branch, from the system prompt to the head
+    pub fn active_branch(&self) -> Vec<usize> {
+        let mut branch: Vec<_> =
+            std::iter::successors(self.messages.get(self.head).map(|_| self.head), |&idx| self.messages[idx].parent)
+                .collect();
+        branch.reverse();
+        branch
+    }
+
+    /// The messages of the active branch
+    pub fn history(&self) -> impl Iterator<Item = &Message> {
+        self.active_branch().into_iter().map(|idx| &self.messages[idx])
+    }
+
+    /// The messages that are sent to the model
+    pub fn chat_messages(&self) -> Vec<ChatMessage> {
+        self.history().map(|m| m.message.clone()).collect()
+    }
+
+    /// Appends a message to the active branch and returns its index
+    pub fn push(&mut self, role: Role, content: String) -> usize {
+        let parent = self.messages.get(self.head).map(|_| self.head);
+        self.messages.push(Message::new(role, content, parent));
+        self.head = self.messages.len() - 1;
+        self.updated_at = Some(Utc::now());
+        self.head
+    }
+
+    /// Milliseconds since the request of the streamed message was sent
+    pub fn request_elapsed_ms(&self) -> Option<u64> {
+        self.request_sent_at.map(|sent_at| sent_at.elapsed().as_millis() as u64)
+    }
+
+    /// Removes the head of the active branch if it's the newest message, e.g. the partial answer of a failed request
+    pub fn discard_head(&mut self) {
+        if self.head + 1 == self.messages.len() {
+            if let Some(parent) = self.messages[self.head].parent {
+                self.messages.pop();
+                self.head = parent;
+            }
+        }
+    }
+
+    /// The messages that follow the message at `idx`, in the order they were created
+    pub fn children(&self, idx: usize) -> Vec<usize> {
+        (idx + 1..self.messages.len()).filter(|&i| self.messages[i].parent == Some(idx)).collect()
+    }
+
+    /// All versions of the message at `idx` (including itself), i.e. the messages with the same parent
+    pub fn versions(&self, idx: usize) -> Vec<usize> {
+        match self.messages[idx].parent {
+            Some(parent) => self.children(parent),
+            None => vec![idx],
+        }
+    }
+
+    /// Makes the previous or next version of the message at `idx` active, together with the newest conversation after it,
+    /// returns the index of that version
+    pub fn switch_version(&mut self, idx: usize, forward: bool) -> usize {
+        let versions = self.versions(idx);
+        let position = versions.iter().position(|&v| v == idx).unwrap_or_default();
+        let position = if forward { position + 1 } else { position.wrapping_sub(1) };
+        let Some(&version) = versions.get(position) else {
+            return idx;
+        };
+        self.show_message(version);
+        version
+    }
+
+    /// Makes the active branch go through the message at `idx`, continuing with the newest conversation after it
+    pub fn show_message(&mut self, idx: usize) {
+        self.head = idx;
+        while let Some(&newest) = self.children(self.head).last() {
+            self.head = newest;
+        }
+    }
+
+    /// The last message of the active branch that has other versions
+    pub fn last_fork(&self) -> Option<usize> {
+        self.active_branch().into_iter().rev().find(|&idx| self.versions(idx).len() > 1)
+    }
+
+    /// The closest message of the active branch before (`forward == false`) or after `idx`, the system prompt is skipped
+    pub fn message_near(&self, idx: usize, forward: bool) -> Option<usize> {
+        let branch = self.active_branch();
+        let position = branch.iter().position(|&i| i == idx)?;
+        let position = if forward { position + 1 } else { position.checked_sub(1)? };
+        branch.get(position).copied().filter(|&i| self.messages[i].parent.is_some())
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct State {
+    pub chats: Vec<Chat>,
+    pub current_chat_id: Option<Uuid>,
+    /// What went wrong while loading, e.g. files that couldn't be parsed, they are shown when the app starts
+    pub problems: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The system prompt (0) with two versions of the conversation, the first user message (1) with its answer (2)
+    /// and the edited user message (3) with its answer (4), which is shown
+    fn edited_chat() -> Chat {
+        let mut chat = Chat::new("You are a test".into());
+        chat.push(Role::User, "Hello".into());
+        chat.push(Role::Assistant, "Hi".into());
+        chat.head = 0;
+        chat.push(Role::User, "Hello there".into());
+        chat.push(Role::Assistant, "General Kenobi".into());
+        chat
+    }
+
+    #[test]
+    fn switches_between_the_versions_of_a_message() {
+        let mut chat = edited_chat();
+        assert_eq!(chat.versions(1), [1, 3]);
+        assert_eq!(chat.versions(0), [0]);
+
+        assert_eq!(chat.switch_version(3, false), 1);
+        assert_eq!(chat.active_branch(), [0, 1, 2]);
+        // there's nothing before the first version
+        assert_eq!(chat.switch_version(1, false), 1);
+        assert_eq!(chat.head, 2);
+        assert_eq!(chat.switch_version(1, true), 3);
+        assert_eq!(chat.active_branch(), [0, 3, 4]);
+        assert_eq!(chat.switch_version(3, true), 3);
+    }
+
+    #[test]
+    fn shows_the_newest_conversation_after_a_message() {
+        let mut chat = edited_chat();
+        chat.show_message(1);
+        assert_eq!(chat.head, 2);
+        chat.show_message(0);
+        assert_eq!(chat.head, 4);
+    }
+
+    #[test]
+    fn discards_only_the_newest_message() {
+        let mut chat = edited_chat();
+        chat.discard_head();
+        assert_eq!((chat.head, chat.messages.len()), (3, 4));
+
+        chat.show_message(1);
+        chat.discard_head();
+        assert_eq!((chat.head, chat.messages.len()), (2, 4));
+
+        // the system prompt stays
+        let mut chat = Chat::new("You are a test".into());
+        chat.discard_head();
+        assert_eq!((chat.head, chat.messages.len()), (0, 1));
+    }
+
+    #[test]
+    fn finds_the_last_fork_and_the_messages_around_a_message() {
+        let chat = edited_chat();
+        assert_eq!(chat.last_fork(), Some(3));
+        assert_eq!(chat.message_near(4, false), Some(3));
+        assert_eq!(chat.message_near(3, true), Some(4));
+        assert_eq!(chat.message_near(4, true), None);
+        // the system prompt is skipped
+        assert_eq!(chat.message_near(3, false), None);
+        // a message that isn't shown
+        assert_eq!(chat.message_near(2, false), None);
+
+        let mut chat = Chat::new("You are a test".into());
+        chat.push(Role::User, "Hello".into());
+        assert_eq!(chat.last_fork(), None);
+    }
+
+    #[test]
+    fn rejects_messages_that_dont_form_a_tree() {
+        assert!(edited_chat().check().is_ok());
+
+        let mut chat = edited_chat();
+        chat.messages.clear();
+        chat.head = 0;
+        assert!(chat.check().is_err());
+
+        let mut chat = edited_chat();
+        chat.head = 5;
+        assert!(chat.check().is_err());
+
+        let mut chat = edited_chat();
+        chat.messages[2].parent = Some(3);
+        assert!(chat.check().is_err());
+    }
+}
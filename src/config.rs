@@ -0,0 +1,603 @@
+use std::{collections::HashMap, path::PathBuf, str::FromStr, sync::Mutex};
+
+use anyhow::{anyhow, bail, Context, Result};
+use chatgpt::prelude::{ChatGPTEngine, ModelConfiguration};
+use serde::{Deserialize, Deserializer, Serialize};
+
+/// The user configuration, usually read from `$XDG_CONFIG_HOME/chatgpt/config.toml` (`~/.config/chatgpt/config.toml`).
+///
+/// Every key is optional, missing keys fall back to the built-in defaults,
+/// unknown keys are rejected so that typos don't go unnoticed.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Model and sampling parameters used for requests
+    pub model: ModelSettings,
+    /// The system message every new chat starts with, `{date}` is replaced with the current date and time
+    pub system_prompt: String,
+    /// Name of the profile in `profiles` that is used
+    pub profile: String,
+    pub profiles: HashMap<String, Profile>,
+    pub theme: Theme,
+    pub paths: Paths,
+    /// How the chats are stored in `paths.state_dir`
+    pub storage: StorageKind,
+    pub backups: BackupSettings,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            model: ModelSettings::default(),
+            system_prompt: "You are ChatGPT, an AI model developed by OpenAI. \
+                Answer as concisely as possible. Today is: {date}"
+                .into(),
+            profile: DEFAULT_PROFILE.into(),
+            profiles: HashMap::new(),
+            theme: Theme::default(),
+            paths: Paths::default(),
+            storage: StorageKind::default(),
+            backups: BackupSettings::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Loads the config at `path`, a missing file results in the default config
+    pub fn load(path: &std::path::Path) -> Result<Self> {
+        match std::fs::read_to_string(path) {
+            Ok(config) => toml::from_str(&config).with_context(|| format!("Invalid config file '{}'", path.display())),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(err) => Err(err).with_context(|| format!("Could not read config file '{}'", path.display())),
+        }
+    }
+
+    /// The active profile, the built-in "openai" profile can be overridden in the config
+    pub fn profile(&self) -> Result<Profile> {
+        match self.profiles.get(&self.profile) {
+            Some(profile) => Ok(profile.clone()),
+            None if self.profile == DEFAULT_PROFILE => Ok(Profile::default()),
+            None => bail!("There's no profile '{}' in the config", self.profile),
+        }
+    }
+
+    /// The model settings of `profile`
+    pub fn model(&self, profile: &Profile) -> ModelSettings {
+        profile.model.resolve(&self.model)
+    }
+
+    /// The engines of the global model settings and of all profiles, e.g. the models of local servers
+    pub fn engines(&self) -> Vec<String> {
+        let profiles = self.profiles.values().filter_map(|profile| profile.model.engine.clone());
+        let mut engines: Vec<_> = std::iter::once(self.model.engine.clone()).chain(profiles).collect();
+        engines.sort();
+        engines.dedup();
+        engines
+    }
+
+    /// Reads the API key of `profile`.
+    ///
+    /// Profiles with a custom `base_url` often don't need a key, so it's only an error if the key file is missing,
+    /// when the profile talks to OpenAI or explicitly specifies `api_key_file`
+    pub fn api_key(&self, profile: &Profile) -> Result<Option<String>> {
+        if profile.backend == BackendKind::Mock {
+            return Ok(None);
+        }
+        let api_key_file = match &profile.api_key_file {
+            Some(file) => expand_home(file)?,
+            None => self.paths.api_key_file()?,
+        };
+        match std::fs::read_to_string(&api_key_file) {
+            Ok(key) => Ok(Some(key.trim().into())),
+            Err(err)
+                if err.kind() == std::io::ErrorKind::NotFound
+                    && profile.base_url.is_some()
+                    && profile.api_key_file.is_none() =>
+            {
+                Ok(None)
+            }
+            Err(err) => {
+                Err(err).with_context(|| format!("Could not read the API key from '{}'", api_key_file.display()))
+            }
+        }
+    }
+
+    /// The system prompt with all placeholders substituted
+    pub fn system_prompt(&self) -> String {
+        self.system_prompt.replace("{date}", &chrono::Local::now().format("%d/%m/%Y %H:%M").to_string())
+    }
+}
+
+const DEFAULT_PROFILE: &str = "openai";
+
+/// Describes which backend is used and where it can be reached, e.g.
+///
+/// ```toml
+/// profile = "local"
+///
+/// [profiles.local]
+/// backend = "openai"
+/// base_url = "http://localhost:8080/v1"
+/// model.engine = "mistral-7b-instruct"
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    pub backend: BackendKind,
+    /// The url the OpenAI API paths (e.g. `/chat/completions`) are appended to, defaults to `https://api.openai.com/v1`
+    pub base_url: Option<String>,
+    /// File containing the API key, defaults to `paths.api_key_file`
+    pub api_key_file: Option<PathBuf>,
+    /// Overrides of the global model settings
+    pub model: ChatModelSettings,
+    /// Settings of the mock backend
+    pub mock: MockSettings,
+    /// Asks for the token usage of streamed answers (`stream_options`), defaults to `true`,
+    /// can be disabled for servers that reject it
+    pub stream_usage: Option<bool>,
+}
+
+impl Profile {
+    pub fn base_url(&self) -> &str {
+        self.base_url.as_deref().unwrap_or("https://api.openai.com/v1")
+    }
+
+    pub fn stream_usage(&self) -> bool {
+        self.stream_usage.unwrap_or(true)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+pub enum BackendKind {
+    /// Any server that speaks the OpenAI `/v1/chat/completions` streaming protocol
+    #[default]
+    #[serde(rename = "openai")]
+    #[value(name = "openai")]
+    OpenAi,
+    /// The `chatgpt_rs` crate, which was used before the other backends existed
+    #[serde(rename = "chatgpt-rs")]
+    ChatGptRs,
+    /// A fake backend that answers with canned responses or echoes the last message, it works offline
+    #[serde(rename = "mock")]
+    Mock,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum StorageKind {
+    /// A TOML file per chat
+    #[default]
+    #[serde(rename = "files")]
+    Files,
+    /// A SQLite database, the chats of the other storage are copied into it when it's created
+    #[serde(rename = "sqlite")]
+    Sqlite,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MockSettings {
+    /// Answers that are cycled through, when empty the last message is echoed
+    pub responses: Vec<String>,
+    /// Delay until the first chunk is sent in milliseconds
+    pub first_chunk_delay_ms: u64,
+    /// Delay between two chunks (words) in milliseconds
+    pub chunk_delay_ms: u64,
+}
+
+impl Default for MockSettings {
+    fn default() -> Self {
+        MockSettings { responses: Vec::new(), first_chunk_delay_ms: 300, chunk_delay_ms: 30 }
+    }
+}
+
+/// Snapshots of all chats in `backups/` of `paths.state_dir`, they are taken when the app starts
+/// and then with the next save after `interval_minutes`, they can be restored with the `restore` command
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BackupSettings {
+    /// How many backups are kept, 0 disables them
+    pub count: usize,
+    pub interval_minutes: u64,
+}
+
+impl Default for BackupSettings {
+    fn default() -> Self {
+        BackupSettings { count: 10, interval_minutes: 60 }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ModelSettings {
+    /// Name of the model, e.g. "gpt-4" or "gpt-3.5-turbo"
+    pub engine: String,
+    pub temperature: f32,
+    pub top_p: f32,
+    pub presence_penalty: f32,
+    pub frequency_penalty: f32,
+    pub max_tokens: Option<u32>,
+}
+
+impl Default for ModelSettings {
+    fn default() -> Self {
+        let defaults = ModelConfiguration::default();
+        ModelSettings {
+            engine: ChatGPTEngine::Gpt4.to_string(),
+            temperature: defaults.temperature,
+            top_p: defaults.top_p,
+            presence_penalty: defaults.presence_penalty,
+            frequency_penalty: defaults.frequency_penalty,
+            max_tokens: None,
+        }
+    }
+}
+
+impl ModelSettings {
+    pub fn engine(&self) -> ChatGPTEngine {
+        engine_from_name(&self.engine)
+    }
+
+    /// The configuration for `chatgpt_rs`, which doesn't support `max_tokens`
+    pub fn model_configuration(&self) -> ModelConfiguration {
+        ModelConfiguration {
+            engine: self.engine(),
+            temperature: self.temperature,
+            top_p: self.top_p,
+            presence_penalty: self.presence_penalty,
+            frequency_penalty: self.frequency_penalty,
+            ..Default::default()
+        }
+    }
+}
+
+/// Model settings of a single chat, every value that is not set falls back to the global [`ModelSettings`]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChatModelSettings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub engine: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f32>,
+    /// 0 removes the limit of the global settings
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+}
+
+impl ChatModelSettings {
+    /// Every value of `settings`, so they're kept when the global settings change later
+    pub fn explicit(settings: &ModelSettings) -> Self {
+        ChatModelSettings {
+            engine: Some(settings.engine.clone()),
+            temperature: Some(settings.temperature),
+            top_p: Some(settings.top_p),
+            presence_penalty: Some(settings.presence_penalty),
+            frequency_penalty: Some(settings.frequency_penalty),
+            max_tokens: Some(settings.max_tokens.unwrap_or(0)),
+        }
+    }
+
+    pub fn resolve(&self, defaults: &ModelSettings) -> ModelSettings {
+        ModelSettings {
+            engine: self.engine.clone().unwrap_or_else(|| defaults.engine.clone()),
+            temperature: self.temperature.unwrap_or(defaults.temperature),
+            top_p: self.top_p.unwrap_or(defaults.top_p),
+            presence_penalty: self.presence_penalty.unwrap_or(defaults.presence_penalty),
+            frequency_penalty: self.frequency_penalty.unwrap_or(defaults.frequency_penalty),
+            max_tokens: match self.max_tokens {
+                Some(0) => None,
+                Some(max_tokens) => Some(max_tokens),
+                None => defaults.max_tokens,
+            },
+        }
+    }
+}
+
+/// Maps a model name to the corresponding engine, unknown names become [`ChatGPTEngine::Custom`]
+pub fn engine_from_name(name: &str) -> ChatGPTEngine {
+    [
+        ChatGPTEngine::Gpt35Turbo,
+        ChatGPTEngine::Gpt35Turbo_0301,
+        ChatGPTEngine::Gpt4,
+        ChatGPTEngine::Gpt4_32k,
+        ChatGPTEngine::Gpt4_0314,
+        ChatGPTEngine::Gpt4_32k_0314,
+    ]
+    .into_iter()
+    .find(|engine| engine.as_ref() == name)
+    .unwrap_or_else(|| ChatGPTEngine::Custom(leak_once(name)))
+}
+
+/// chatgpt_rs wants a &'static str for custom model names, every request needs one, so each name is only leaked once
+fn leak_once(name: &str) -> &'static str {
+    static LEAKED: Mutex<Vec<&'static str>> = Mutex::new(Vec::new());
+    let mut leaked = LEAKED.lock().unwrap_or_else(|err| err.into_inner());
+    if let Some(leaked) = leaked.iter().find(|&&leaked| leaked == name) {
+        return leaked;
+    }
+    let name = Box::leak(name.to_string().into_boxed_str());
+    leaked.push(name);
+    name
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Theme {
+    pub headers: ThemeColor,
+    pub bold: ThemeColor,
+    pub italic: ThemeColor,
+    pub scrollbar: ThemeColor,
+    pub input: ThemeColor,
+    pub chat_background: ThemeColor,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme {
+            headers: ThemeColor::DarkYellow,
+            bold: ThemeColor::DarkYellow,
+            italic: ThemeColor::DarkMagenta,
+            scrollbar: ThemeColor::DarkYellow,
+            input: ThemeColor::DarkBlue,
+            chat_background: ThemeColor::Black,
+        }
+    }
+}
+
+/// A terminal color, written either as crossterm color name (e.g. "dark_yellow"), as "#rrggbb" or as ansi value (0-255)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThemeColor {
+    Black,
+    DarkGrey,
+    Red,
+    DarkRed,
+    Green,
+    DarkGreen,
+    Yellow,
+    DarkYellow,
+    Blue,
+    DarkBlue,
+    Magenta,
+    DarkMagenta,
+    Cyan,
+    DarkCyan,
+    White,
+    Grey,
+    Rgb(u8, u8, u8),
+    AnsiValue(u8),
+}
+
+impl FromStr for ThemeColor {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        use ThemeColor::*;
+        Ok(match s.to_lowercase().replace(['-', ' '], "_").as_str() {
+            "black" => Black,
+            "dark_grey" | "dark_gray" => DarkGrey,
+            "red" => Red,
+            "dark_red" => DarkRed,
+            "green" => Green,
+            "dark_green" => DarkGreen,
+            "yellow" => Yellow,
+            "dark_yellow" => DarkYellow,
+            "blue" => Blue,
+            "dark_blue" => DarkBlue,
+            "magenta" => Magenta,
+            "dark_magenta" => DarkMagenta,
+            "cyan" => Cyan,
+            "dark_cyan" => DarkCyan,
+            "white" => White,
+            "grey" | "gray" => Grey,
+            hex if hex.starts_with('#') => {
+                if hex.len() != 7 || !hex.is_ascii() {
+                    bail!("Invalid color '{s}', expected the format '#rrggbb'");
+                }
+                let channel =
+                    |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| anyhow!("Invalid hex color '{s}'"));
+                Rgb(channel(1)?, channel(3)?, channel(5)?)
+            }
+            ansi => AnsiValue(ansi.parse().map_err(|_| anyhow!("Unknown color '{s}'"))?),
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for ThemeColor {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl From<ThemeColor> for termimad::crossterm::style::Color {
+    fn from(color: ThemeColor) -> Self {
+        use termimad::crossterm::style::Color;
+        match color {
+            ThemeColor::Black => Color::Black,
+            ThemeColor::DarkGrey => Color::DarkGrey,
+            ThemeColor::Red => Color::Red,
+            ThemeColor::DarkRed => Color::DarkRed,
+            ThemeColor::Green => Color::Green,
+            ThemeColor::DarkGreen => Color::DarkGreen,
+            ThemeColor::Yellow => Color::Yellow,
+            ThemeColor::DarkYellow => Color::DarkYellow,
+            ThemeColor::Blue => Color::Blue,
+            ThemeColor::DarkBlue => Color::DarkBlue,
+            ThemeColor::Magenta => Color::Magenta,
+            ThemeColor::DarkMagenta => Color::DarkMagenta,
+            ThemeColor::Cyan => Color::Cyan,
+            ThemeColor::DarkCyan => Color::DarkCyan,
+            ThemeColor::White => Color::White,
+            ThemeColor::Grey => Color::Grey,
+            ThemeColor::Rgb(r, g, b) => Color::Rgb { r, g, b },
+            ThemeColor::AnsiValue(v) => Color::AnsiValue(v),
+        }
+    }
+}
+
+impl From<ThemeColor> for ratatui::style::Color {
+    fn from(color: ThemeColor) -> Self {
+        use ratatui::style::Color;
+        // ratatui uses different names than crossterm (e.g. Red is DarkRed in crossterm)
+        match color {
+            ThemeColor::Black => Color::Black,
+            ThemeColor::DarkGrey => Color::DarkGray,
+            ThemeColor::Red => Color::LightRed,
+            ThemeColor::DarkRed => Color::Red,
+            ThemeColor::Green => Color::LightGreen,
+            ThemeColor::DarkGreen => Color::Green,
+            ThemeColor::Yellow => Color::LightYellow,
+            ThemeColor::DarkYellow => Color::Yellow,
+            ThemeColor::Blue => Color::LightBlue,
+            ThemeColor::DarkBlue => Color::Blue,
+            ThemeColor::Magenta => Color::LightMagenta,
+            ThemeColor::DarkMagenta => Color::Magenta,
+            ThemeColor::Cyan => Color::LightCyan,
+            ThemeColor::DarkCyan => Color::Cyan,
+            ThemeColor::White => Color::White,
+            ThemeColor::Grey => Color::Gray,
+            ThemeColor::Rgb(r, g, b) => Color::Rgb(r, g, b),
+            ThemeColor::AnsiValue(v) => Color::Indexed(v),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Paths {
+    /// Directory where the chats are stored, defaults to `$XDG_DATA_HOME/chatgpt` (`~/.local/share/chatgpt`).
+    /// It contains an `index.toml` with the order of the chats and a file per chat in `chats/`
+    pub state_dir: Option<PathBuf>,
+    /// File containing the OpenAI API key, defaults to `$XDG_CONFIG_HOME/chatgpt/api-key` (`~/.config/chatgpt/api-key`)
+    pub api_key_file: Option<PathBuf>,
+    /// Directory where chats are exported to as Markdown, defaults to the current directory
+    pub export_dir: Option<PathBuf>,
+}
+
+impl Paths {
+    pub fn state_dir(&self) -> Result<PathBuf> {
+        match &self.state_dir {
+            Some(dir) => expand_home(dir),
+            None => data_dir(),
+        }
+    }
+
+    pub fn export_dir(&self) -> Result<PathBuf> {
+        match &self.export_dir {
+            Some(dir) => expand_home(dir),
+            None => Ok(std::env::current_dir()?),
+        }
+    }
+
+    pub fn api_key_file(&self) -> Result<PathBuf> {
+        match &self.api_key_file {
+            Some(file) => expand_home(file),
+            None => Ok(config_dir()?.join("api-key")),
+        }
+    }
+}
+
+pub fn home_dir() -> Result<PathBuf> {
+    Ok(PathBuf::from(std::env::var("HOME").context("$HOME is not set")?))
+}
+
+/// `$XDG_CONFIG_HOME/chatgpt`, or `~/.config/chatgpt` if it's not set or if only that one exists
+pub fn config_dir() -> Result<PathBuf> {
+    xdg_dir("XDG_CONFIG_HOME", ".config")
+}
+
+/// `$XDG_DATA_HOME/chatgpt`, or `~/.local/share/chatgpt` if it's not set or if only that one exists
+pub fn data_dir() -> Result<PathBuf> {
+    xdg_dir("XDG_DATA_HOME", ".local/share")
+}
+
+/// The `chatgpt` directory in `$var`, or in `~/<default>` if it's not set or only the one in `~/<default>` exists
+fn xdg_dir(var: &str, default: &str) -> Result<PathBuf> {
+    let legacy = home_dir().map(|home| home.join(default).join("chatgpt"));
+    pick_xdg_dir(std::env::var_os(var).map(PathBuf::from), legacy)
+}
+
+/// The `chatgpt` directory in `xdg`, the value of the variable, unless `legacy` should be used instead
+fn pick_xdg_dir(xdg: Option<PathBuf>, legacy: Result<PathBuf>) -> Result<PathBuf> {
+    // relative paths are invalid according to the XDG base directory specification and are ignored
+    match xdg.filter(|dir| dir.is_absolute()) {
+        Some(dir) => {
+            let dir = dir.join("chatgpt");
+            // versions before the variables were honoured always used `~/<default>`, their files are still found
+            match legacy {
+                Ok(legacy) if !dir.exists() && legacy.exists() => Ok(legacy),
+                _ => Ok(dir),
+            }
+        }
+        None => legacy,
+    }
+}
+
+fn expand_home(path: &std::path::Path) -> Result<PathBuf> {
+    match path.strip_prefix("~") {
+        Ok(rest) => Ok(home_dir()?.join(rest)),
+        Err(_) => Ok(path.to_path_buf()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::TempDir;
+
+    #[test]
+    fn uses_the_xdg_directory_of_the_variable() {
+        let dir = TempDir::new();
+        let (xdg, legacy) = (dir.join("xdg"), dir.join("home").join(".config").join("chatgpt"));
+        assert_eq!(pick_xdg_dir(Some(xdg.clone()), Ok(legacy.clone())).unwrap(), xdg.join("chatgpt"));
+        assert_eq!(pick_xdg_dir(None, Ok(legacy.clone())).unwrap(), legacy);
+        // without a home directory
+        assert_eq!(pick_xdg_dir(Some(xdg.clone()), Err(anyhow!("no home"))).unwrap(), xdg.join("chatgpt"));
+    }
+
+    #[test]
+    fn ignores_a_relative_xdg_directory() {
+        let legacy = PathBuf::from("/home/test/.config/chatgpt");
+        assert_eq!(pick_xdg_dir(Some("relative/config".into()), Ok(legacy.clone())).unwrap(), legacy);
+        assert!(pick_xdg_dir(Some("relative/config".into()), Err(anyhow!("no home"))).is_err());
+    }
+
+    #[test]
+    fn keeps_using_the_legacy_directory_until_the_xdg_one_exists() {
+        let dir = TempDir::new();
+        let (xdg, legacy) = (dir.join("xdg"), dir.join("home").join(".config").join("chatgpt"));
+        std::fs::create_dir_all(&legacy).unwrap();
+        assert_eq!(pick_xdg_dir(Some(xdg.clone()), Ok(legacy.clone())).unwrap(), legacy);
+
+        std::fs::create_dir_all(xdg.join("chatgpt")).unwrap();
+        assert_eq!(pick_xdg_dir(Some(xdg.clone()), Ok(legacy)).unwrap(), xdg.join("chatgpt"));
+    }
+
+    #[test]
+    fn leaks_a_custom_model_name_only_once() {
+        let ChatGPTEngine::Custom(first) = engine_from_name("my-local-model") else { panic!("not a custom engine") };
+        let ChatGPTEngine::Custom(second) = engine_from_name("my-local-model") else { panic!("not a custom engine") };
+        assert!(std::ptr::eq(first, second));
+        assert!(matches!(engine_from_name("gpt-4"), ChatGPTEngine::Gpt4));
+    }
+
+    #[test]
+    fn explicit_chat_settings_dont_follow_the_global_settings() {
+        let picked = ModelSettings { max_tokens: Some(512), ..ModelSettings::default() };
+        let chat = ChatModelSettings::explicit(&picked);
+        let changed = ModelSettings { engine: "gpt-3.5-turbo".into(), temperature: 1.5, ..ModelSettings::default() };
+        assert_eq!(chat.resolve(&changed), picked);
+    }
+
+    #[test]
+    fn explicit_chat_settings_can_remove_the_token_limit() {
+        let chat = ChatModelSettings::explicit(&ModelSettings::default());
+        let limited = ModelSettings { max_tokens: Some(256), ..ModelSettings::default() };
+        assert_eq!(chat.resolve(&limited).max_tokens, None);
+        assert_eq!(ChatModelSettings::default().resolve(&limited).max_tokens, Some(256));
+    }
+}
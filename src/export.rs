@@ -0,0 +1,114 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+
+use crate::{chat::Chat, config::ModelSettings};
+
+/// Renders the active branch of the chat as a Markdown document with a YAML front matter,
+/// the messages are written verbatim, so code blocks stay intact
+pub fn to_markdown(chat: &Chat, model: &ModelSettings) -> String {
+    let mut markdown = String::from("---\n");
+    markdown += &format!("id: {}\n", chat.id);
+    markdown += &format!("title: {}\n", yaml_string(&chat.title));
+    markdown += &format!("model: {}\n", yaml_string(&model.engine));
+    if let Some(created_at) = chat.created_at {
+        markdown += &format!("created: {}\n", created_at.to_rfc3339());
+    }
+    if let Some(updated_at) = chat.updated_at {
+        markdown += &format!("updated: {}\n", updated_at.to_rfc3339());
+    }
+    if !chat.tags.is_empty() {
+        let tags: Vec<_> = chat.tags.iter().map(|t| yaml_string(t)).collect();
+        markdown += &format!("tags: [{}]\n", tags.join(", "));
+    }
+    markdown += "---\n\n";
+    markdown += &format!("# {}\n", chat.title.replace('\n', " "));
+
+    for message in chat.history() {
+        markdown += &format!("\n## {:?}\n\n{}\n", message.role, message.content.trim_end());
+    }
+    markdown
+}
+
+/// A double quoted YAML string
+fn yaml_string(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n"))
+}
+
+/// A file name for the chat that is derived from its title, e.g. `rust-lifetimes.md`
+pub fn file_name(chat: &Chat) -> String {
+    let mut name = String::new();
+    for c in chat.title.chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            name.push(c);
+        } else if !name.is_empty() && !name.ends_with('-') {
+            name.push('-');
+        }
+    }
+    let name: String = name.trim_end_matches('-').chars().take(60).collect();
+    let name = name.trim_end_matches('-');
+    if name.is_empty() {
+        format!("{}.md", chat.id)
+    } else {
+        format!("{name}.md")
+    }
+}
+
+/// Writes the chat into `dir`, returns the path of the file.
+/// If another chat with the same title was exported there, the start of the id is added to the file name
+pub fn export_to_dir(chat: &Chat, model: &ModelSettings, dir: &Path) -> Result<PathBuf> {
+    std::fs::create_dir_all(dir).with_context(|| format!("Couldn't create {}", dir.display()))?;
+    let mut path = dir.join(file_name(chat));
+    if path.exists() && !is_export_of(&path, chat) {
+        let name = file_name(chat);
+        let short_id = &chat.id.to_string()[..8];
+        path = dir.join(format!("{}-{short_id}.md", name.trim_end_matches(".md")));
+    }
+    std::fs::write(&path, to_markdown(chat, model)).with_context(|| format!("Couldn't write {}", path.display()))?;
+    Ok(path)
+}
+
+/// The file is an export of the chat, i.e. its front matter has the id of the chat
+fn is_export_of(path: &Path, chat: &Chat) -> bool {
+    let id_line = format!("id: {}", chat.id);
+    std::fs::read_to_string(path)
+        .map_or(false, |markdown| markdown.lines().skip(1).take_while(|l| *l != "---").any(|l| l == id_line))
+}
+
+/// Finds the chat by its id, a unique prefix of its id or its exact title
+pub fn find_chat<'a>(chats: &'a [Chat], query: &str) -> Result<&'a Chat> {
+    if let Some(chat) = chats.iter().find(|c| c.id.to_string() == query || c.title == query) {
+        return Ok(chat);
+    }
+    let matches: Vec<_> = chats.iter().filter(|c| c.id.to_string().starts_with(query)).collect();
+    match matches.as_slice() {
+        [chat] => Ok(chat),
+        [] => bail!("There's no chat with the id or title '{query}'"),
+        _ => bail!("'{query}' matches {} chats, use more of the id", matches.len()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::TempDir;
+
+    #[test]
+    fn chats_with_the_same_title_get_their_own_files() {
+        let dir = TempDir::new();
+        let model = ModelSettings::default();
+        let mut first = Chat::new(String::new());
+        first.title = "Rust lifetimes".to_string();
+        let mut second = Chat::new(String::new());
+        second.title = first.title.clone();
+
+        let first_path = export_to_dir(&first, &model, &dir).unwrap();
+        let second_path = export_to_dir(&second, &model, &dir).unwrap();
+        assert_eq!(first_path, dir.join("rust-lifetimes.md"));
+        assert_eq!(second_path, dir.join(format!("rust-lifetimes-{}.md", &second.id.to_string()[..8])));
+        // exporting again replaces the chat's own file
+        assert_eq!(export_to_dir(&first, &model, &dir).unwrap(), first_path);
+        assert_eq!(export_to_dir(&second, &model, &dir).unwrap(), second_path);
+        assert!(std::fs::read_to_string(&first_path).unwrap().contains(&first.id.to_string()));
+    }
+}
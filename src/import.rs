@@ -0,0 +1,220 @@
+//! Import of the `conversations.json` of the ChatGPT data export (Settings → Data controls → Export data)
+
+use std::{collections::HashMap, path::Path};
+
+use anyhow::{Context, Result};
+use chatgpt::types::Role;
+use chrono::{DateTime, TimeZone, Utc};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::chat::{Chat, Message, State};
+
+#[derive(Deserialize)]
+struct Conversation {
+    #[serde(alias = "conversation_id")]
+    id: String,
+    #[serde(default)]
+    title: Option<String>,
+    create_time: Option<f64>,
+    update_time: Option<f64>,
+    /// The nodes of the message tree by their id
+    mapping: HashMap<String, Node>,
+    /// The last node of the branch that was shown
+    current_node: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Node {
+    message: Option<ExportedMessage>,
+    parent: Option<String>,
+    #[serde(default)]
+    children: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct ExportedMessage {
+    author: Author,
+    create_time: Option<f64>,
+    content: Content,
+    #[serde(default)]
+    metadata: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+struct Author {
+    role: String,
+}
+
+#[derive(Deserialize)]
+struct Content {
+    content_type: String,
+    #[serde(default)]
+    parts: Vec<serde_json::Value>,
+    /// Set instead of `parts` for code
+    #[serde(default)]
+    text: Option<String>,
+}
+
+impl ExportedMessage {
+    /// The role and text of the message, `None` for messages that weren't shown in the conversation,
+    /// e.g. tool calls, hidden system messages or images without text
+    fn to_chat_message(&self) -> Option<(Role, String)> {
+        let role = match self.author.role.as_str() {
+            "system" => Role::System,
+            "user" => Role::User,
+            "assistant" => Role::Assistant,
+            _ => return None,
+        };
+        if self.metadata.get("is_visually_hidden_from_conversation").and_then(|h| h.as_bool()) == Some(true) {
+            return None;
+        }
+        let content = match self.content.content_type.as_str() {
+            "text" | "multimodal_text" => {
+                let parts: Vec<_> = self.content.parts.iter().filter_map(|p| p.as_str()).collect();
+                parts.join("\n")
+            }
+            "code" => format!("```\n{}\n```", self.content.text.as_deref()?.trim_end()),
+            _ => return None,
+        };
+        (!content.trim().is_empty()).then_some((role, content))
+    }
+}
+
+fn timestamp(seconds: Option<f64>) -> Option<DateTime<Utc>> {
+    let seconds = seconds?;
+    Utc.timestamp_opt(seconds.trunc() as i64, (seconds.fract() * 1e9) as u32).single()
+}
+
+/// Converts the conversation into a chat with the same message tree, messages that aren't shown are left out.
+/// The root is the system message of the conversation, or an empty one, ChatGPT doesn't export its own prompt
+fn to_chat(conversation: &Conversation) -> Result<Chat> {
+    let mut chat = Chat::new(String::new());
+    chat.messages[0].created_at = None;
+    // the chat keeps the id of the conversation, so it's recognized when it's imported again
+    chat.id = Uuid::parse_str(&conversation.id)
+        .with_context(|| format!("The conversation id '{}' isn't a UUID", conversation.id))?;
+    chat.title = conversation.title.clone().filter(|t| !t.trim().is_empty()).unwrap_or_else(|| chat.id.to_string());
+    chat.created_at = timestamp(conversation.create_time);
+    chat.updated_at = timestamp(conversation.update_time);
+
+    // the index of the message every node corresponds to, a left out node corresponds to its closest ancestor
+    let mut indices: HashMap<&str, usize> = HashMap::new();
+    let mut roots: Vec<_> = conversation
+        .mapping
+        .iter()
+        .filter(|(_, node)| node.parent.as_ref().map_or(true, |parent| !conversation.mapping.contains_key(parent)))
+        .map(|(id, _)| id.as_str())
+        .collect();
+    roots.sort();
+    // depth first, so that the children of a node get ascending indices in their original order
+    let mut stack: Vec<(&str, usize)> = roots.into_iter().rev().map(|id| (id, 0)).collect();
+    while let Some((id, parent)) = stack.pop() {
+        let Some(node) = conversation.mapping.get(id) else {
+            continue;
+        };
+        let idx = match node.message.as_ref().and_then(ExportedMessage::to_chat_message) {
+            Some((Role::System, content)) if parent == 0 && chat.messages.len() == 1 => {
+                chat.messages[0].content = content;
+                chat.messages[0].created_at = timestamp(node.message.as_ref().and_then(|m| m.create_time));
+                0
+            }
+            Some((role, content)) => {
+                let mut message = Message::new(role, content, Some(parent));
+                message.created_at = timestamp(node.message.as_ref().and_then(|m| m.create_time));
+                chat.messages.push(message);
+                chat.messages.len() - 1
+            }
+            None => parent,
+        };
+        indices.insert(id, idx);
+        stack.extend(node.children.iter().rev().map(|child| (child.as_str(), idx)));
+    }
+
+    match conversation.current_node.as_deref().and_then(|node| indices.get(node)) {
+        Some(&head) => chat.head = head,
+        None => chat.show_message(0),
+    }
+    Ok(chat)
+}
+
+/// Adds the conversations of the export that aren't in `state` yet,
+/// returns how many were imported and how many were skipped, because they were already imported
+pub fn import(path: &Path, state: &mut State) -> Result<(usize, usize)> {
+    let json = std::fs::read_to_string(path).with_context(|| format!("Couldn't read {}", path.display()))?;
+    let conversations: Vec<Conversation> =
+        serde_json::from_str(&json).with_context(|| format!("{} isn't a ChatGPT data export", path.display()))?;
+
+    let (mut imported, mut skipped) = (0, 0);
+    for conversation in &conversations {
+        let chat = to_chat(conversation)?;
+        if state.chats.iter().any(|c| c.id == chat.id) {
+            skipped += 1;
+            continue;
+        }
+        state.chats.push(chat);
+        imported += 1;
+    }
+    // the chat list is ordered by age
+    state.chats.sort_by_key(|chat| chat.created_at);
+    Ok((imported, skipped))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn conversation(system_prompt: Option<&str>) -> Conversation {
+        let system = system_prompt.map(|content| {
+            serde_json::json!({
+                "message": {
+                    "author": { "role": "system" },
+                    "create_time": 1680000000.0,
+                    "content": { "content_type": "text", "parts": [content] },
+                },
+                "parent": "root",
+                "children": ["user"],
+            })
+        });
+        let mut mapping = serde_json::json!({
+            "root": { "message": null, "parent": null, "children": [if system.is_some() { "system" } else { "user" }] },
+            "user": {
+                "message": {
+                    "author": { "role": "user" },
+                    "create_time": 1680000001.0,
+                    "content": { "content_type": "text", "parts": ["Hello"] },
+                },
+                "parent": if system.is_some() { "system" } else { "root" },
+                "children": [],
+            },
+        });
+        if let Some(system) = system {
+            mapping["system"] = system;
+        }
+        serde_json::from_value(serde_json::json!({
+            "id": "0f2c4a8e-1111-4a7b-9c3d-123456789abc",
+            "title": "Greeting",
+            "create_time": 1680000000.0,
+            "mapping": mapping,
+            "current_node": "user",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn imports_a_conversation_below_a_neutral_root() {
+        let chat = to_chat(&conversation(None)).unwrap();
+        assert_eq!((chat.messages[0].role, chat.messages[0].content.as_str()), (Role::System, ""));
+        assert_eq!(chat.messages[0].created_at, None);
+        assert_eq!((chat.messages[1].content.as_str(), chat.messages[1].parent), ("Hello", Some(0)));
+        assert_eq!(chat.head, 1);
+    }
+
+    #[test]
+    fn keeps_the_system_message_of_the_conversation() {
+        let chat = to_chat(&conversation(Some("Be brief"))).unwrap();
+        assert_eq!(chat.messages.len(), 2);
+        assert_eq!(chat.messages[0].content, "Be brief");
+        assert_eq!(chat.messages[0].created_at, timestamp(Some(1680000000.0)));
+    }
+}
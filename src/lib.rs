@@ -0,0 +1,3 @@
+//! Only the mock server is a library, so the `mock-server` binary and the tests of the backends can share it
+#[cfg(feature = "mock-server")]
+pub mod mock_server;
@@ -1,45 +1,90 @@
-use std::path::PathBuf;
+mod backend;
+mod chat;
+mod config;
+mod export;
+mod import;
+mod model_picker;
+mod search;
+mod storage;
+#[cfg(test)]
+mod tests;
 
-use anyhow::{bail, Result};
-use chatgpt::{
-    prelude::{ChatGPT, ChatGPTEngine, ModelConfiguration},
-    types::{ChatMessage, ResponseChunk, Role},
-};
+use anyhow::{bail, Context, Result};
+use chatgpt::types::{ChatMessage, Role};
+use clap::Parser;
 use crossterm::{
     event::{
         self, // , DisableMouseCapture, EnableMouseCapture
         Event,
         KeyCode,
+        KeyModifiers,
     },
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
     QueueableCommand,
 };
-use futures::{future::FutureExt, StreamExt};
+use futures::{
+    future::{AbortHandle, Abortable, BoxFuture, FutureExt},
+    StreamExt,
+};
 use futures_util::stream::FuturesUnordered;
 use ratatui::{
     backend::{Backend, CrosstermBackend},
     layout::{Constraint, Direction, Layout, Rect},
-    style::{Color, Modifier, Style},
+    style::{Modifier, Style},
+    text::{Span, Spans},
     widgets::{Block, Borders, List, ListItem, ListState, Paragraph},
     Frame, Terminal,
 };
-use serde::{Deserialize, Serialize};
-use std::io::Write;
+use std::{collections::HashMap, io::Write, path::PathBuf};
 use tokio::{
     select,
     sync::{mpsc, watch},
 };
 use uuid::Uuid;
 
+use crate::{
+    backend::{ChatBackend, RequestError, ResponseChunk, ResponseStream},
+    chat::{Chat, State},
+    config::{BackendKind, ChatModelSettings, Config, ModelSettings, Theme},
+    model_picker::{model_picker_ui, ModelPicker, ModelPickerEvent},
+    search::{find_ignore_case, fuzzy_match, search_ui, Search, SearchEvent},
+    storage::{Backups, NewerVersion, Storage},
+};
+
+/// How often an answer is saved while it's streamed
+const STREAM_SAVE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// Sent after an answer that was cut off, to get the rest of it
+const CONTINUE_PROMPT: &str =
+    "Your last answer was cut off. Continue it exactly where it stopped, without repeating anything or commenting on it.";
+
 #[derive(Debug)]
 enum UiMode {
     ChatSelection,
     Chat,
+    /// Editing the title of the selected chat in the chat list
+    ChatRename,
+    /// Editing the comma separated tags of the selected chat in the chat list
+    ChatTags,
+    /// Searching the messages of all chats
+    Search,
+    /// Narrowing the chat list down by typing parts of the titles, like fzf
+    ChatFilter,
+    /// Selecting a message of the current chat, to edit and resend it or to switch between its versions
+    MessageSelection,
     // Help,
 }
 
-#[derive(Debug)]
+/// A row of the chat list
+enum ChatListRow {
+    /// The heading of the chats with the tag, or of the ones without any tag
+    Section { tag: Option<String>, count: usize },
+    /// `matched` are the positions of the chars in the title that match the fuzzy filter
+    Chat { idx: usize, matched: Vec<usize> },
+}
+
+#[derive(Debug, Clone, Copy)]
 enum ChatGPTMessageChunkType {
     Chat { chat_id: Uuid, message_id: usize },
     ChatTitle { chat_id: Uuid },
@@ -51,40 +96,63 @@ enum AppMessage {
     // KeyEvent(crossterm::event::KeyEvent),
     ResizeEvent, // TODO dimensions
     ChatGPTMessageChunkReceived { message_type: ChatGPTMessageChunkType, chunk: ResponseChunk },
+    RequestFailed { message_type: ChatGPTMessageChunkType, error: RequestError },
 }
 
 #[derive(Debug)]
 enum ChatGPTMessage {
-    ChatRequest { id: Uuid, messages: Vec<ChatMessage> },
+    ChatRequest {
+        id: Uuid,
+        /// Index the answer will have in the messages of the chat
+        message_id: usize,
+        messages: Vec<ChatMessage>,
+        model: ModelSettings,
+    },
     // TODO make system_message configurable? Or just hardcode it?
-    ChatTitleRequest { id: Uuid, system_message: String },
-    // TODO
-    // ChangeModelConfiguration(ModelConfiguration),
-}
-
-// TODO support more than one reply count
-#[derive(Debug, Serialize, Deserialize)]
-struct Chat {
-    history: Vec<ChatMessage>,
-    title: String,
-    /// Current value of the input box
-    input: String,
-    input_pos: usize,
-    scroll: usize,
-    id: Uuid,
-}
-
-#[derive(Default, Debug, Serialize, Deserialize)]
-struct State {
-    chats: Vec<Chat>,
-    current_chat_id: Option<Uuid>,
+    ChatTitleRequest {
+        id: Uuid,
+        system_message: String,
+    },
+    ChangeModelConfiguration(ModelSettings),
+    /// Stops streaming the answer of the chat
+    CancelRequest {
+        id: Uuid,
+    },
+    /// Stops all streams of the chat, including its title
+    DeleteChat {
+        id: Uuid,
+    },
 }
 
 /// App holds the state of the application
 struct App {
     ui_mode: UiMode,
 
+    config: Config,
+    /// The model settings that are currently used for requests
+    model: ModelSettings,
+    /// When set, the model picker popup is shown and receives all key events
+    model_picker: Option<ModelPicker>,
+    /// When set, a popup asks whether this chat should be deleted and receives all key events
+    delete_confirmation: Option<Uuid>,
+    /// The text that is edited in `UiMode::ChatRename` and `UiMode::ChatTags`
+    list_input: String,
+    /// Only chats with this tag are listed
+    tag_filter: Option<String>,
+    /// The chat list shows the archived chats instead of the others
+    show_archived: bool,
+    /// The result of the last action in the chat list, e.g. where a chat was exported to, it's shown until a key is pressed
+    status: Option<String>,
+    /// The row of the chat list that is selected, as a chat can be listed in the sections of several tags
+    chat_list_row: usize,
+    search: Search,
+    /// The query in `UiMode::ChatFilter`
+    chat_filter: String,
+
     state: State,
+    storage: Box<dyn Storage>,
+    /// Streamed answers are saved every [`STREAM_SAVE_INTERVAL`]
+    last_save: Option<std::time::Instant>,
 
     // This is quite a hack to get termimad working with ratatui,
     // as termimad directly writes to stdout, while ratatui is buffered
@@ -102,13 +170,29 @@ struct App {
 
 impl App {
     pub fn new(
+        config: Config,
+        model: ModelSettings,
         state: State,
+        storage: Box<dyn Storage>,
         app_message_receiver: mpsc::UnboundedReceiver<AppMessage>,
         chatgpt_message_sender: mpsc::UnboundedSender<ChatGPTMessage>,
         quit_signal_sender: watch::Sender<()>,
     ) -> Self {
         App {
+            model,
+            model_picker: None,
+            delete_confirmation: None,
+            list_input: String::new(),
+            tag_filter: None,
+            show_archived: false,
+            status: None,
+            chat_list_row: 0,
+            search: Search::default(),
+            chat_filter: String::new(),
+            config,
             state,
+            storage,
+            last_save: None,
             ui_mode: UiMode::ChatSelection,
             app_message_receiver,
             chatgpt_message_sender,
@@ -117,14 +201,9 @@ impl App {
         }
     }
 
-    pub fn save_state(&self) -> Result<()> {
-        let state_dir = PathBuf::from(std::env::var("HOME")?).join(".local/share/chatgpt");
-        std::fs::create_dir_all(&state_dir)?;
-
-        let state = toml::to_string_pretty(&self.state)?;
-
-        let mut f = std::fs::File::create(state_dir.join("state.toml"))?;
-        Ok(f.write_all(state.as_bytes())?)
+    pub fn save_state(&mut self) -> Result<()> {
+        self.last_save = Some(std::time::Instant::now());
+        self.storage.save(&self.state)
     }
 
     pub fn current_chat_idx(&self) -> Option<usize> {
@@ -136,6 +215,108 @@ impl App {
             }
         })
     }
+    /// All tags of the listed (archived or not archived) chats, sorted
+    pub fn tags(&self) -> Vec<String> {
+        let chats = self.state.chats.iter().filter(|c| c.archived == self.show_archived);
+        let tags: std::collections::BTreeSet<_> = chats.flat_map(|c| c.tags.iter()).collect();
+        tags.into_iter().cloned().collect()
+    }
+    /// The indices of the chats that are listed, i.e. archived or not and with the tag of the tag filter, newest first
+    fn listed_chats(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.state.chats.len()).rev().filter(|&idx| {
+            let chat = &self.state.chats[idx];
+            chat.archived == self.show_archived && self.tag_filter.as_ref().map_or(true, |tag| chat.tags.contains(tag))
+        })
+    }
+    /// The rows of the chat list, pinned and then newest chats first, grouped by tags as soon as any chat has a tag
+    fn chat_list_rows(&self) -> Vec<ChatListRow> {
+        if matches!(self.ui_mode, UiMode::ChatFilter) {
+            let chats = self.filtered_chats().into_iter();
+            return chats.map(|(idx, matched)| ChatListRow::Chat { idx, matched }).collect();
+        }
+        let mut chats: Vec<_> = self.listed_chats().collect();
+        // the sort is stable, so the chats stay ordered by age otherwise
+        chats.sort_by_key(|&idx| !self.state.chats[idx].pinned);
+        let tags = self.tags();
+        if self.tag_filter.is_some() || tags.is_empty() {
+            return chats.into_iter().map(|idx| ChatListRow::Chat { idx, matched: Vec::new() }).collect();
+        }
+
+        let mut rows = Vec::new();
+        let sections = tags.into_iter().map(Some).chain([None]);
+        for tag in sections {
+            let section: Vec<_> = chats
+                .iter()
+                .copied()
+                .filter(|&idx| match &tag {
+                    Some(tag) => self.state.chats[idx].tags.contains(tag),
+                    None => self.state.chats[idx].tags.is_empty(),
+                })
+                .collect();
+            if !section.is_empty() {
+                rows.push(ChatListRow::Section { tag, count: section.len() });
+                rows.extend(section.into_iter().map(|idx| ChatListRow::Chat { idx, matched: Vec::new() }));
+            }
+        }
+        rows
+    }
+    /// The selected row of the chat list, i.e. the remembered row if it still shows the current chat,
+    /// otherwise the first row of the current chat
+    fn selected_chat_list_row(&self, rows: &[ChatListRow]) -> Option<usize> {
+        let is_current = |row: &ChatListRow| matches!(row, ChatListRow::Chat { idx, .. } if Some(self.state.chats[*idx].id) == self.state.current_chat_id);
+        match rows.get(self.chat_list_row) {
+            Some(row) if is_current(row) => Some(self.chat_list_row),
+            _ => rows.iter().position(is_current),
+        }
+    }
+    /// Selects the first chat of the chat list if the current chat isn't listed (anymore)
+    fn ensure_chat_list_selection(&mut self) {
+        let rows = self.chat_list_rows();
+        if self.selected_chat_list_row(&rows).is_none() {
+            self.move_chat_list_selection(true);
+        }
+    }
+    /// Selects the previous or next chat of the chat list, wraps around at the end
+    fn move_chat_list_selection(&mut self, forward: bool) {
+        let rows = self.chat_list_rows();
+        let chat_rows: Vec<_> = (0..rows.len()).filter(|&i| matches!(rows[i], ChatListRow::Chat { .. })).collect();
+        let Some(&last) = chat_rows.last() else {
+            return;
+        };
+        let row = match self.selected_chat_list_row(&rows) {
+            Some(row) if forward => chat_rows.iter().copied().find(|&r| r > row).unwrap_or(chat_rows[0]),
+            Some(row) => chat_rows.iter().copied().rev().find(|&r| r < row).unwrap_or(last),
+            None => chat_rows[0],
+        };
+        if let ChatListRow::Chat { idx, .. } = rows[row] {
+            self.chat_list_row = row;
+            self.state.current_chat_id = Some(self.state.chats[idx].id);
+        }
+    }
+    /// The indices of the listed chats whose titles match the filter,
+    /// best matches first (newer chats first if they're equal),
+    /// together with the positions of the matched chars in the titles
+    pub fn filtered_chats(&self) -> Vec<(usize, Vec<usize>)> {
+        let mut matches: Vec<_> = self
+            .listed_chats()
+            .filter_map(|idx| {
+                let (score, positions) = fuzzy_match(&self.state.chats[idx].title, &self.chat_filter)?;
+                Some((score, idx, positions))
+            })
+            .collect();
+        // the sort is stable, so newer chats stay in front
+        matches.sort_by_key(|(score, ..)| std::cmp::Reverse(*score));
+        matches.into_iter().map(|(_, idx, positions)| (idx, positions)).collect()
+    }
+    /// Selects the best match of the filter, unless the current chat matches as well
+    fn select_filtered_chat(&mut self) {
+        let filtered = self.filtered_chats();
+        if !filtered.iter().any(|(idx, _)| Some(self.state.chats[*idx].id) == self.state.current_chat_id) {
+            if let Some((idx, _)) = filtered.first() {
+                self.state.current_chat_id = Some(self.state.chats[*idx].id);
+            }
+        }
+    }
     pub fn current_chat(&self) -> Option<&Chat> {
         self.state.current_chat_id.and_then(|id| self.chat(id))
     }
@@ -148,59 +329,253 @@ impl App {
     pub fn chat(&self, id: Uuid) -> Option<&Chat> {
         self.state.chats.iter().find(|chat| chat.id == id)
     }
+    /// The model settings of the current chat, or the global settings if there's no current chat
+    pub fn current_model(&self) -> ModelSettings {
+        self.current_chat().map(|chat| chat.model.resolve(&self.model)).unwrap_or_else(|| self.model.clone())
+    }
+    /// Sends the history of the chat to get the next answer
+    pub fn request_answer(&mut self, chat_id: Uuid) {
+        self.request(chat_id, false);
+    }
+    /// Asks for the rest of the answer at the head of the chat, e.g. one that was cut off when the app was closed
+    pub fn continue_answer(&mut self, chat_id: Uuid) {
+        self.request(chat_id, true);
+    }
+    /// Streams an answer into a new message, or into the head of the chat if `continue_head` is set
+    fn request(&mut self, chat_id: Uuid, continue_head: bool) {
+        let defaults = self.model.clone();
+        if let Some(chat) = self.chat_mut(chat_id) {
+            let mut messages = chat.chat_messages();
+            let message_id = if continue_head {
+                messages.push(ChatMessage { role: Role::User, content: CONTINUE_PROMPT.into() });
+                chat.head
+            } else {
+                chat.messages.len()
+            };
+            chat.streaming = Some(message_id);
+            chat.continuing = continue_head;
+            chat.request_sent_at = Some(std::time::Instant::now());
+            let request =
+                ChatGPTMessage::ChatRequest { id: chat_id, message_id, messages, model: chat.model.resolve(&defaults) };
+            self.chatgpt_message_sender.send(request).ok();
+        }
+    }
+    /// Stops streaming the answer of the chat, what was received so far is kept
+    pub fn cancel_request(&mut self, chat_id: Uuid) {
+        if let Some(chat) = self.chat_mut(chat_id) {
+            if let Some(message_id) = chat.streaming.take() {
+                let duration_ms = chat.request_elapsed_ms();
+                if let Some(message) = chat.messages.get_mut(message_id) {
+                    message.stopped = true;
+                    message.incomplete = false;
+                    message.duration_ms = duration_ms;
+                }
+                self.chatgpt_message_sender.send(ChatGPTMessage::CancelRequest { id: chat_id }).ok();
+            }
+        }
+    }
+    /// Generates a title for the chat from its active branch, unless the title was set by the user
+    pub fn request_title(&mut self, chat_id: Uuid) {
+        let Some(chat) = self.chat(chat_id).filter(|c| !c.manual_title) else {
+            return;
+        };
+        let mut system_message = "Provide a useful and very descriptive title with max 4 words for the following chat,\
+            where System: <text> at the beginning of a line describes what you, the Assistant should be, and
+            User: <text> at the beginning of a line denotes what the User asked,\
+            and Assistant: <text> at the beginning of a line denotes what your answer is,\
+            everything after the following colon is the chat:\n"
+            .to_string();
+
+        for message in chat.history() {
+            system_message += &format!("{:?}: {}", message.role, message.content)
+        }
+
+        self.chatgpt_message_sender.send(ChatGPTMessage::ChatTitleRequest { id: chat_id, system_message }).ok();
+    }
+    /// Deletes the chat, if it was selected the chat that takes its place in the chat list is selected
+    pub fn delete_chat(&mut self, chat_id: Uuid) {
+        if let Some(idx) = self.state.chats.iter().position(|c| c.id == chat_id) {
+            let row = self.selected_chat_list_row(&self.chat_list_rows());
+            self.state.chats.remove(idx);
+            self.chatgpt_message_sender.send(ChatGPTMessage::DeleteChat { id: chat_id }).ok();
+            if self.state.current_chat_id == Some(chat_id) {
+                // the chat below the deleted one moved up into its row, or it was the last one
+                let rows = self.chat_list_rows();
+                let (above, below) = rows.split_at(row.unwrap_or_default().min(rows.len()));
+                let next = below.iter().chain(above.iter().rev()).find_map(|row| match row {
+                    ChatListRow::Chat { idx, .. } => Some(self.state.chats[*idx].id),
+                    ChatListRow::Section { .. } => None,
+                });
+                self.state.current_chat_id = next;
+            }
+            self.ensure_chat_list_selection();
+        }
+    }
+    /// Creates a new chat with the configured system prompt and selects it
+    pub fn new_chat(&mut self) -> Uuid {
+        let chat = Chat::new(self.config.system_prompt());
+        let id = chat.id;
+        self.state.chats.push(chat);
+        self.state.current_chat_id = Some(id);
+        id
+    }
+}
+
+/// A terminal UI for ChatGPT and other OpenAI compatible chat APIs
+#[derive(Debug, Parser)]
+#[command(version, about)]
+struct Args {
+    /// Overrides the backend of the active profile, `mock` works offline and without an API key
+    #[arg(long, value_enum)]
+    backend: Option<BackendKind>,
+    /// The config file, defaults to `$XDG_CONFIG_HOME/chatgpt/config.toml` (`~/.config/chatgpt/config.toml`)
+    #[arg(long, env = "CHATGPT_TUI_CONFIG")]
+    config: Option<PathBuf>,
+    /// Overrides `paths.state_dir` of the config, the directory where the chats are stored,
+    /// e.g. to keep the chats of a project apart
+    #[arg(long, env = "CHATGPT_TUI_DATA_DIR")]
+    data_dir: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum Command {
+    /// Writes a chat as a Markdown document
+    Export {
+        /// The id (or a unique prefix of it) or the exact title of the chat
+        chat: String,
+        /// The file to write to, the document is printed if it's not set
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Imports the chats of the `conversations.json` in a ChatGPT data export,
+    /// chats that were already imported are skipped
+    Import {
+        /// The `conversations.json`
+        file: PathBuf,
+    },
+    /// Lists the backups of the chats, or replaces all chats with the chats of a backup
+    Restore {
+        /// The number of the backup in the list or its file name
+        backup: Option<String>,
+    },
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // TODO support cross platform config/state loading
-    let mut state: State =
-        std::fs::read_to_string(PathBuf::from(std::env::var("HOME")?).join(".local/share/chatgpt/state.toml"))
-            .map_err(anyhow::Error::from)
-            .and_then(|s| Ok(toml::from_str(&s)?))
-            .unwrap_or_default();
-
-    let api_key =
-        std::fs::read_to_string(PathBuf::from(std::env::var("HOME")?).join(".config/chatgpt/api-key"))?.trim().into();
-
-    // let config = std::fs::read_to_string(PathBuf::from(std::env::var("HOME")?).join(".config/chatgpt/config.toml"))?
-    //     .trim()
-    //     .into();
-
-    // state.current_chat_id = None;
-    let id = Uuid::new_v4();
-    state.current_chat_id = Some(id);
-
-    state.chats.push(Chat {
-        title: id.to_string(),
-        id,
-        scroll: 0,
-        input: String::new(),
-        input_pos: 0,
-        history: vec![ChatMessage {
-            role: chatgpt::types::Role::System,
-            content: format!(
-                "You are ChatGPT, an AI model developed by OpenAI. \
-                    Answer as concisely as possible. Today is: {0}",
-                chrono::Local::now().format("%d/%m/%Y %H:%M")
-            ),
-        }],
-    });
+    let args = Args::parse();
+
+    let config_file = match args.config {
+        Some(file) => file,
+        None => config::config_dir()?.join("config.toml"),
+    };
+    let mut config = Config::load(&config_file)?;
+    if let Some(data_dir) = args.data_dir {
+        config.paths.state_dir = Some(data_dir);
+    }
+
+    let mut backups = Backups::new(&config.paths.state_dir()?, config.backups.clone());
+
+    // before the chats are loaded, restoring has to work when they can't be loaded anymore
+    if let Some(Command::Restore { backup }) = &args.command {
+        let Some(backup) = backup else {
+            for (i, backup) in backups.list()?.iter().enumerate() {
+                let chats = backup.read().map_or_else(|err| format!("{err:#}"), |s| format!("{} chats", s.chats.len()));
+                println!("{:>3}  {}  {chats}", i + 1, backup.created_at.format("%Y-%m-%d %H:%M:%S"));
+            }
+            return Ok(());
+        };
+        let backup = backups.find(backup)?;
+        let restored = backup.read()?;
+        // opened only now, so the backups can still be listed when the storage can't be opened
+        let mut storage = storage::from_config(&config).context("The backup wasn't restored")?;
+        let backed_up = match storage.load() {
+            Ok(state) => {
+                state.problems.iter().for_each(|problem| eprintln!("{problem}"));
+                backups.create(&state).map(|_| ())
+            }
+            Err(err) if err.is::<NewerVersion>() => return Err(err.context("The backup wasn't restored")),
+            Err(err) => Err(err),
+        };
+        if let Err(err) = &backed_up {
+            eprintln!("The chats before the restore couldn't be backed up: {err:#}");
+        }
+        storage.save(&restored)?;
+        println!(
+            "Restored {} chats from the backup of {}{}",
+            restored.chats.len(),
+            backup.created_at.format("%Y-%m-%d %H:%M:%S"),
+            if backed_up.is_ok() { ", the chats before were backed up too" } else { "" }
+        );
+        return Ok(());
+    }
+
+    let mut storage = storage::from_config(&config)?;
+    let mut state = storage.load()?;
+    backups.create(&state)?;
+    // the subcommands don't show the popup of the ui
+    if args.command.is_some() {
+        state.problems.iter().for_each(|problem| eprintln!("{problem}"));
+    }
+
+    let mut profile = config.profile()?;
+    if let Some(backend) = args.backend {
+        profile.backend = backend;
+    }
+    let model = config.model(&profile);
+
+    match args.command {
+        Some(Command::Export { chat, output }) => {
+            let chat = export::find_chat(&state.chats, &chat)?;
+            let markdown = export::to_markdown(chat, &chat.model.resolve(&model));
+            match output {
+                Some(output) => {
+                    std::fs::write(&output, markdown).with_context(|| format!("Couldn't write {}", output.display()))?
+                }
+                None => print!("{markdown}"),
+            }
+            return Ok(());
+        }
+        Some(Command::Import { file }) => {
+            let (imported, skipped) = import::import(&file, &mut state)?;
+            storage.save(&state)?;
+            println!("Imported {imported} chats, skipped {skipped} that were already imported");
+            return Ok(());
+        }
+        Some(Command::Restore { .. }) | None => {}
+    }
+
+    let backend = backend::from_profile(&profile, config.api_key(&profile)?)?;
 
     let (app_message_sender, app_message_receiver) = mpsc::unbounded_channel();
     let (chatgpt_message_sender, chatgpt_message_receiver) = mpsc::unbounded_channel();
     let (quit_signal_sender, quit_signal_receiver) = watch::channel(());
 
-    let mut app = App::new(state, app_message_receiver, chatgpt_message_sender, quit_signal_sender);
+    let mut app = App::new(
+        config,
+        model.clone(),
+        state,
+        backups.with_storage(storage),
+        app_message_receiver,
+        chatgpt_message_sender,
+        quit_signal_sender,
+    );
 
+    app.new_chat();
     app.ui_mode = UiMode::Chat;
 
     let mut set = tokio::task::JoinSet::new();
     set.spawn(run_app(app));
-    set.spawn(handle_input(app_message_sender.clone(), quit_signal_receiver.clone()));
-    set.spawn(handle_chatgpt(api_key, app_message_sender, chatgpt_message_receiver, quit_signal_receiver));
+    set.spawn(handle_input(event::EventStream::new(), app_message_sender.clone(), quit_signal_receiver.clone()));
+    set.spawn(handle_chatgpt(backend, model, app_message_sender, chatgpt_message_receiver, quit_signal_receiver));
 
     while let Some(res) = set.join_next().await {
-        res??;
+        if let Err(err) = res.map_err(anyhow::Error::from).and_then(|res| res) {
+            // the ui may still be running, so it can't restore the terminal itself
+            restore_terminal().ok();
+            return Err(err);
+        }
     }
     Ok(())
 }
@@ -213,125 +588,376 @@ async fn run_app(mut app: App) -> Result<()> {
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
 
-    // main loop
-    loop {
-        terminal.draw(|f| ui(f, &mut app))?;
-        // let the hackery begin
-        if let Some(draw_chat_area) = app.draw_chat_area.take() {
-            let scroll = if let Some(chat) = app.current_chat() { chat.scroll } else { 0 };
-            let scroll = draw_chat_area(scroll);
-            if let Some(chat) = app.current_chat_mut() {
-                chat.scroll = scroll;
-            }
-            if matches!(app.ui_mode, UiMode::Chat) {
-                if let Some(chat) = app.current_chat() {
-                    let wrapped_input =
-                        textwrap::wrap(chat.input.trim(), textwrap::Options::new(terminal.size()?.width as usize));
-                    let trailing_whitespace_count = chat.input.len() - chat.input.trim_end().len();
-                    let term_height = terminal.size()?.height;
-                    terminal.backend_mut().queue(crossterm::cursor::MoveTo(
-                        wrapped_input[wrapped_input.len() - 1].len() as u16 + trailing_whitespace_count as u16,
-                        term_height + wrapped_input.len() as u16 - 1 - 4,
-                    ))?;
-                    terminal.backend_mut().queue(crossterm::cursor::Show)?;
-                    terminal.backend_mut().queue(crossterm::cursor::EnableBlinking)?;
-                    std::io::Write::flush(&mut terminal.backend_mut())?;
-                }
-            } else {
-                terminal.backend_mut().queue(crossterm::cursor::Hide)?;
+    let result = app_loop(&mut terminal, &mut app, draw_chat_area).await;
+    if result.is_err() {
+        app.quit_signal_sender.send(()).ok();
+    }
+
+    // cleanup
+    restore_terminal()?;
+    result
+}
+
+/// Restores the terminal, even if the app stopped unexpectedly
+fn restore_terminal() -> Result<()> {
+    disable_raw_mode()?;
+    execute!(std::io::stdout(), LeaveAlternateScreen, crossterm::cursor::Show)?; // DisableMouseCapture
+    Ok(())
+}
+
+/// Lets termimad draw the chat area over what ratatui has drawn, and puts the cursor into the input box
+fn draw_chat_area(terminal: &mut Terminal<CrosstermBackend<std::io::Stdout>>, app: &mut App) -> Result<()> {
+    // let the hackery begin
+    if let Some(draw_chat_area) = app.draw_chat_area.take() {
+        let scroll = if let Some(chat) = app.current_chat() { chat.scroll } else { 0 };
+        let scroll = draw_chat_area(scroll);
+        if let Some(chat) = app.current_chat_mut() {
+            chat.scroll = scroll;
+        }
+        if matches!(app.ui_mode, UiMode::Chat | UiMode::MessageSelection) {
+            if let Some(chat) = app.current_chat() {
+                let wrapped_input =
+                    textwrap::wrap(chat.input.trim(), textwrap::Options::new(terminal.size()?.width as usize));
+                let trailing_whitespace_count = chat.input.len() - chat.input.trim_end().len();
+                let term_height = terminal.size()?.height;
+                terminal.backend_mut().queue(crossterm::cursor::MoveTo(
+                    wrapped_input[wrapped_input.len() - 1].len() as u16 + trailing_whitespace_count as u16,
+                    term_height + wrapped_input.len() as u16 - 1 - 4,
+                ))?;
+                terminal.backend_mut().queue(crossterm::cursor::Show)?;
+                terminal.backend_mut().queue(crossterm::cursor::EnableBlinking)?;
                 std::io::Write::flush(&mut terminal.backend_mut())?;
             }
+        } else {
+            terminal.backend_mut().queue(crossterm::cursor::Hide)?;
+            std::io::Write::flush(&mut terminal.backend_mut())?;
         }
+    }
+    Ok(())
+}
+
+/// Draws the app and handles its messages until it's quit,
+/// `draw_chat_area` draws what ratatui can't, it's only possible on a real terminal
+async fn app_loop<B: Backend>(
+    terminal: &mut Terminal<B>,
+    app: &mut App,
+    draw_chat_area: fn(&mut Terminal<B>, &mut App) -> Result<()>,
+) -> Result<()> {
+    // main loop
+    loop {
+        terminal.draw(|f| ui(f, app))?;
+        draw_chat_area(terminal, app)?;
 
         match app.app_message_receiver.recv().await {
+            Some(AppMessage::KeyEvent(key)) if app.model_picker.is_some() => {
+                match app.model_picker.as_mut().unwrap().handle_key(key.code) {
+                    ModelPickerEvent::None => {}
+                    ModelPickerEvent::Apply(model) => {
+                        if let Some(chat_id) = app.model_picker.take().unwrap().chat_id {
+                            if let Some(chat) = app.chat_mut(chat_id) {
+                                chat.model = ChatModelSettings::explicit(&model);
+                            }
+                            app.save_state()?;
+                        } else {
+                            app.chatgpt_message_sender
+                                .send(ChatGPTMessage::ChangeModelConfiguration(model.clone()))
+                                .ok();
+                            app.model = model;
+                        }
+                    }
+                    ModelPickerEvent::Cancel => app.model_picker = None,
+                }
+            }
+            // the problems while loading are shown until a key is pressed
+            Some(AppMessage::KeyEvent(_)) if !app.state.problems.is_empty() => app.state.problems.clear(),
+            Some(AppMessage::KeyEvent(key)) if app.delete_confirmation.is_some() => match key.code {
+                KeyCode::Char('y') | KeyCode::Enter => {
+                    let chat_id = app.delete_confirmation.take().unwrap();
+                    app.delete_chat(chat_id);
+                    app.save_state()?;
+                }
+                KeyCode::Char('n') | KeyCode::Esc => app.delete_confirmation = None,
+                _ => {}
+            },
+            // the first key after an action only hides its status
+            Some(AppMessage::KeyEvent(_)) if app.status.is_some() => app.status = None,
             Some(AppMessage::KeyEvent(key)) => match (&app.ui_mode, key.code) {
                 (UiMode::ChatSelection, KeyCode::Enter) => {
                     app.ui_mode = UiMode::Chat;
                 }
-                (UiMode::ChatSelection, KeyCode::Up) => {
-                    if let Some(idx) = app.current_chat_idx() {
-                        let new_idx = if idx == app.state.chats.len() - 1 { 0 } else { idx + 1 };
-                        app.state.current_chat_id = Some(app.state.chats[new_idx].id);
-                    } else if !app.state.chats.is_empty() {
-                        app.state.current_chat_id = Some(app.state.chats[app.state.chats.len() - 1].id);
-                    }
-                }
-                (UiMode::ChatSelection, KeyCode::Down) => {
-                    if let Some(idx) = app.current_chat_idx() {
-                        let new_idx = if idx == 0 { app.state.chats.len() - 1 } else { idx - 1 };
-                        app.state.current_chat_id = Some(app.state.chats[new_idx].id);
-                    } else if !app.state.chats.is_empty() {
-                        app.state.current_chat_id = Some(app.state.chats[app.state.chats.len() - 1].id);
-                    }
+                (UiMode::ChatSelection, KeyCode::Up | KeyCode::Down) => {
+                    app.move_chat_list_selection(key.code == KeyCode::Down);
                 }
                 // KeyCode::Char('h') => {
                 //     app.ui_mode = UiMode::Help;
                 // }
+                (UiMode::ChatSelection, KeyCode::Char('m')) => {
+                    app.model_picker = Some(ModelPicker::new(None, app.model.clone(), app.config.engines()));
+                }
                 (UiMode::ChatSelection, KeyCode::Char('n')) => {
-                    let id = Uuid::new_v4();
-                    app.state.current_chat_id = Some(id);
-
-                    app.state.chats.push(Chat {
-                        title: id.to_string(),
-                        id,
-                        scroll: 0,
-                        input: String::new(),
-                        input_pos: 0,
-                        history: vec![ChatMessage {
-                            role: chatgpt::types::Role::System,
-                            content: format!(
-                                "You are ChatGPT, an AI model developed by OpenAI. \
-                                    Answer as concisely as possible. Today is: {0}",
-                                chrono::Local::now().format("%d/%m/%Y %H:%M")
-                            ),
-                        }],
-                    });
+                    app.new_chat();
                     app.ui_mode = UiMode::Chat;
                 }
+                (UiMode::ChatSelection, KeyCode::Char('f')) => {
+                    app.ui_mode = UiMode::ChatFilter;
+                }
+                (UiMode::ChatSelection, KeyCode::Char('/')) => {
+                    app.search.update(&app.state.chats);
+                    app.ui_mode = UiMode::Search;
+                }
+                (UiMode::ChatSelection, KeyCode::Char('r')) => {
+                    if let Some(chat) = app.current_chat() {
+                        app.list_input = chat.title.clone();
+                        app.ui_mode = UiMode::ChatRename;
+                    }
+                }
+                // generates a new title from the current conversation, also replaces titles set by the user
+                (UiMode::ChatSelection, KeyCode::Char('t')) => {
+                    if let Some(chat) = app.current_chat_mut() {
+                        chat.manual_title = false;
+                        let chat_id = chat.id;
+                        app.request_title(chat_id);
+                    }
+                }
+                (UiMode::ChatSelection, KeyCode::Char('a')) => {
+                    if let Some(chat) = app.current_chat() {
+                        app.list_input = chat.tags.join(", ");
+                        app.ui_mode = UiMode::ChatTags;
+                    }
+                }
+                // only lists the chats of one tag, cycles through all tags and then back to all chats
+                (UiMode::ChatSelection, KeyCode::Char('#')) => {
+                    let tags = app.tags();
+                    let next = match &app.tag_filter {
+                        Some(tag) => tags.iter().position(|t| t == tag).map_or(0, |i| i + 1),
+                        None => 0,
+                    };
+                    app.tag_filter = tags.get(next).cloned();
+                    app.ensure_chat_list_selection();
+                }
+                (UiMode::ChatSelection, KeyCode::Char('e')) => {
+                    if let Some(chat) = app.current_chat() {
+                        let model = chat.model.resolve(&app.model);
+                        let result =
+                            app.config.paths.export_dir().and_then(|dir| export::export_to_dir(chat, &model, &dir));
+                        app.status = Some(match result {
+                            Ok(path) => format!("Exported to {}", path.display()),
+                            Err(err) => format!("Export failed: {err:#}"),
+                        });
+                    }
+                }
+                (UiMode::ChatSelection, KeyCode::Char('p')) => {
+                    if let Some(chat) = app.current_chat_mut() {
+                        chat.pinned = !chat.pinned;
+                        app.save_state()?;
+                    }
+                }
+                // archives the chat, or restores it in the archive
+                (UiMode::ChatSelection, KeyCode::Char('x')) => {
+                    if let Some(chat) = app.current_chat_mut() {
+                        chat.archived = !chat.archived;
+                        app.ensure_chat_list_selection();
+                        app.save_state()?;
+                    }
+                }
+                (UiMode::ChatSelection, KeyCode::Char('v')) => {
+                    app.show_archived = !app.show_archived;
+                    app.tag_filter = None;
+                    app.ensure_chat_list_selection();
+                }
+                (UiMode::ChatSelection, KeyCode::Char('d') | KeyCode::Delete) => {
+                    app.delete_confirmation = app.state.current_chat_id;
+                }
                 (UiMode::ChatSelection, KeyCode::Esc | KeyCode::Char('q')) => {
                     app.quit_signal_sender.send(()).ok();
                     break;
                 }
                 (UiMode::ChatSelection, _) => {}
+                (UiMode::ChatRename, KeyCode::Enter) => {
+                    let title = std::mem::take(&mut app.list_input).trim().to_string();
+                    if let Some(chat) = app.current_chat_mut().filter(|_| !title.is_empty()) {
+                        chat.title = title;
+                        chat.manual_title = true;
+                        app.save_state()?;
+                    }
+                    app.ui_mode = UiMode::ChatSelection;
+                }
+                (UiMode::ChatRename, KeyCode::Esc) => {
+                    app.list_input.clear();
+                    app.ui_mode = UiMode::ChatSelection;
+                }
+                (UiMode::ChatRename, KeyCode::Char(c)) => app.list_input.push(c),
+                (UiMode::ChatRename, KeyCode::Backspace) => {
+                    app.list_input.pop();
+                }
+                (UiMode::ChatRename, _) => {}
+                (UiMode::ChatTags, KeyCode::Enter) => {
+                    let input = std::mem::take(&mut app.list_input);
+                    if let Some(chat) = app.current_chat_mut() {
+                        chat.tags.clear();
+                        for tag in input.split(',').map(str::trim).filter(|t| !t.is_empty()) {
+                            if !chat.tags.iter().any(|t| t == tag) {
+                                chat.tags.push(tag.to_string());
+                            }
+                        }
+                        app.save_state()?;
+                    }
+                    app.ui_mode = UiMode::ChatSelection;
+                }
+                (UiMode::ChatTags, KeyCode::Esc) => {
+                    app.list_input.clear();
+                    app.ui_mode = UiMode::ChatSelection;
+                }
+                (UiMode::ChatTags, KeyCode::Char(c)) => app.list_input.push(c),
+                (UiMode::ChatTags, KeyCode::Backspace) => {
+                    app.list_input.pop();
+                }
+                (UiMode::ChatTags, _) => {}
+                (UiMode::ChatFilter, KeyCode::Up | KeyCode::Down) => {
+                    let filtered = app.filtered_chats();
+                    let position = filtered
+                        .iter()
+                        .position(|(idx, _)| Some(app.state.chats[*idx].id) == app.state.current_chat_id);
+                    let position = match (position, key.code) {
+                        (Some(position), KeyCode::Up) => position.checked_sub(1).unwrap_or(filtered.len() - 1),
+                        (Some(position), _) => (position + 1) % filtered.len(),
+                        (None, _) => 0,
+                    };
+                    if let Some((idx, _)) = filtered.get(position) {
+                        app.state.current_chat_id = Some(app.state.chats[*idx].id);
+                    }
+                }
+                (UiMode::ChatFilter, KeyCode::Enter) => {
+                    if app.current_chat_idx().map_or(false, |idx| app.filtered_chats().iter().any(|(i, _)| *i == idx)) {
+                        app.chat_filter.clear();
+                        app.ui_mode = UiMode::Chat;
+                    }
+                }
+                (UiMode::ChatFilter, KeyCode::Esc) => {
+                    app.chat_filter.clear();
+                    app.ui_mode = UiMode::ChatSelection;
+                }
+                (UiMode::ChatFilter, KeyCode::Char(c)) => {
+                    app.chat_filter.push(c);
+                    app.select_filtered_chat();
+                }
+                (UiMode::ChatFilter, KeyCode::Backspace) => {
+                    app.chat_filter.pop();
+                    app.select_filtered_chat();
+                }
+                (UiMode::ChatFilter, _) => {}
+                (UiMode::Search, _) => match app.search.handle_key(key.code, &app.state.chats) {
+                    SearchEvent::None => {}
+                    SearchEvent::Open { chat_id, message_idx } => {
+                        let query = app.search.query().to_string();
+                        if let Some(chat) = app.chat_mut(chat_id).filter(|c| c.streaming.is_none()) {
+                            chat.show_message(message_idx);
+                        }
+                        if let Some(chat) = app.chat_mut(chat_id) {
+                            chat.highlight = Some((message_idx, query));
+                            chat.scroll_to_highlight = true;
+                        }
+                        app.state.current_chat_id = Some(chat_id);
+                        app.ui_mode = UiMode::Chat;
+                    }
+                    SearchEvent::Cancel => app.ui_mode = UiMode::ChatSelection,
+                },
                 (UiMode::Chat, KeyCode::Enter) => {
                     if app.state.current_chat_id.is_none() {
-                        let id = Uuid::new_v4();
-                        app.state.current_chat_id = Some(id);
-
-                        app.state.chats.push(Chat {
-                            title: id.to_string(),
-                            id,
-                            scroll: 0,
-                            input: String::new(),
-                            input_pos: 0,
-                            history: vec![ChatMessage {
-                                role: chatgpt::types::Role::System,
-                                content: format!(
-                                    "You are ChatGPT, an AI model developed by OpenAI. \
-                                        Answer as concisely as possible. Today is: {0}",
-                                    chrono::Local::now().format("%d/%m/%Y %H:%M")
-                                ),
-                            }],
-                        });
+                        app.new_chat();
                     }
 
                     let chat_id = app.state.current_chat_id.unwrap();
 
+                    app.cancel_request(chat_id);
                     if let Some(chat) = app.state.chats.iter_mut().find(|c| c.id == chat_id) {
-                        chat.history.push(ChatMessage {
-                            role: chatgpt::types::Role::User,
-                            content: chat.input.drain(..).collect(),
-                        });
+                        // the edited message becomes a new version, the conversation after the old one is kept
+                        if let Some(parent) = chat.editing.take().and_then(|idx| chat.messages[idx].parent) {
+                            chat.head = parent;
+                        }
+                        let input = std::mem::take(&mut chat.input);
+                        chat.push(Role::User, input);
                         chat.input_pos = 0;
-
-                        app.chatgpt_message_sender
-                            .send(ChatGPTMessage::ChatRequest { id: chat_id, messages: chat.history.clone() })
-                            .ok();
+                        chat.error = None;
                     } else {
                         bail!("There's no chat with id: '{}'", chat_id)
                     }
+                    app.request_answer(chat_id);
                     app.save_state()?;
                 }
+                (UiMode::Chat, KeyCode::Char('e')) if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    if let Some(chat) = app.current_chat_mut() {
+                        chat.selected_message =
+                            chat.active_branch().into_iter().rev().find(|&idx| chat.messages[idx].role == Role::User);
+                        if chat.selected_message.is_some() {
+                            app.ui_mode = UiMode::MessageSelection;
+                        }
+                    }
+                }
+                (UiMode::Chat, KeyCode::Esc) if app.current_chat().map_or(false, |c| c.editing.is_some()) => {
+                    if let Some(chat) = app.current_chat_mut() {
+                        chat.editing = None;
+                        chat.input.clear();
+                        chat.input_pos = 0;
+                    }
+                }
+                (UiMode::Chat, KeyCode::Char('p')) if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    app.model_picker =
+                        Some(ModelPicker::new(app.state.current_chat_id, app.current_model(), app.config.engines()));
+                }
+                (UiMode::Chat, KeyCode::Char('c')) if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    if let Some(chat_id) = app.state.current_chat_id {
+                        app.cancel_request(chat_id);
+                        app.save_state()?;
+                    }
+                }
+                // retries a failed request, or regenerates the last answer and keeps the previous one as another version
+                (UiMode::Chat, KeyCode::Char('r')) if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    if let Some(chat) = app.current_chat_mut().filter(|c| c.streaming.is_none()) {
+                        let failed = chat.error.take().is_some();
+                        if failed && chat.continuing {
+                            // the answer that was continued existed before the request, so it's continued again
+                            let chat_id = chat.id;
+                            app.continue_answer(chat_id);
+                        } else {
+                            if failed && chat.messages[chat.head].role != Role::User {
+                                // a partial answer of a stream that failed midway is dropped
+                                chat.discard_head();
+                            }
+                            let last_user_message = chat
+                                .active_branch()
+                                .into_iter()
+                                .rev()
+                                .find(|&idx| chat.messages[idx].role == Role::User);
+                            if let Some(last_user_message) = last_user_message {
+                                chat.head = last_user_message;
+                                let chat_id = chat.id;
+                                app.request_answer(chat_id);
+                                app.save_state()?;
+                            }
+                        }
+                    }
+                }
+                // continues an answer that is incomplete because the app was closed while it was streamed, or that was stopped
+                (UiMode::Chat, KeyCode::Char('o')) if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    if let Some(chat) = app.current_chat_mut().filter(|c| c.streaming.is_none() && c.error.is_none()) {
+                        let head = &chat.messages[chat.head];
+                        if head.role == Role::Assistant && (head.incomplete || head.stopped) {
+                            let chat_id = chat.id;
+                            app.continue_answer(chat_id);
+                        }
+                    }
+                }
+                // switches between the versions of the last message that has some, e.g. regenerated answers
+                (UiMode::Chat, KeyCode::Left | KeyCode::Right) if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    if let Some(chat) = app.current_chat_mut().filter(|c| c.streaming.is_none()) {
+                        if let Some(fork) = chat.last_fork() {
+                            chat.switch_version(fork, key.code == KeyCode::Right);
+                            chat.error = None;
+                            app.save_state()?;
+                        }
+                    }
+                }
                 (UiMode::Chat, KeyCode::Char(c)) => {
                     if let Some(chat) = app.current_chat_mut() {
                         chat.input.push(c);
@@ -346,8 +972,11 @@ async fn run_app(mut app: App) -> Result<()> {
                 }
                 (UiMode::Chat, KeyCode::Esc) => {
                     app.ui_mode = UiMode::ChatSelection;
+                    if let Some(chat) = app.current_chat_mut() {
+                        chat.highlight = None;
+                    }
                     if let Some(chat) = app.current_chat() {
-                        if chat.history.len() > 1 {
+                        if chat.messages.len() > 1 {
                             app.save_state()?;
                         }
                     }
@@ -363,46 +992,94 @@ async fn run_app(mut app: App) -> Result<()> {
                     }
                 }
                 (UiMode::Chat, _) => {}
+                (UiMode::MessageSelection, KeyCode::Up | KeyCode::Down) => {
+                    if let Some(chat) = app.current_chat_mut() {
+                        if let Some(selected) = chat.selected_message {
+                            let next = chat.message_near(selected, key.code == KeyCode::Down);
+                            chat.selected_message = next.or(Some(selected));
+                        }
+                    }
+                }
+                // switches to another version of the selected message, and the conversation after it
+                (UiMode::MessageSelection, KeyCode::Left | KeyCode::Right) => {
+                    if let Some(chat) = app.current_chat_mut().filter(|c| c.streaming.is_none()) {
+                        if let Some(selected) = chat.selected_message {
+                            chat.selected_message = Some(chat.switch_version(selected, key.code == KeyCode::Right));
+                            chat.error = None;
+                            app.save_state()?;
+                        }
+                    }
+                }
+                (UiMode::MessageSelection, KeyCode::Enter) => {
+                    if let Some(chat) = app.current_chat_mut() {
+                        if let Some(selected) =
+                            chat.selected_message.take().filter(|&idx| chat.messages[idx].role == Role::User)
+                        {
+                            chat.input = chat.messages[selected].content.clone();
+                            chat.input_pos = chat.input.len();
+                            chat.editing = Some(selected);
+                        }
+                    }
+                    app.ui_mode = UiMode::Chat;
+                }
+                (UiMode::MessageSelection, KeyCode::Esc) => {
+                    if let Some(chat) = app.current_chat_mut() {
+                        chat.selected_message = None;
+                    }
+                    app.ui_mode = UiMode::Chat;
+                }
+                (UiMode::MessageSelection, _) => {}
             },
             Some(AppMessage::ChatGPTMessageChunkReceived {
                 message_type: ChatGPTMessageChunkType::Chat { chat_id, message_id },
                 chunk,
             }) => {
-                // TODO handle properly (delete stream),
-                // when a chat may have been deleted while still transferring chunks for this chat still...
-                // not sure, if it's worth the effort though
-
-                if let Some(chat) = app.chat_mut(chat_id) {
+                let defaults = app.model.clone();
+                // chunks that were already on their way when the request was cancelled
+                // or the chat was deleted are dropped
+                if let Some(chat) = app.chat_mut(chat_id).filter(|c| c.streaming == Some(message_id)) {
                     match chunk {
                         ResponseChunk::BeginResponse { role, .. } => {
-                            chat.history.push(ChatMessage { role, content: String::new() });
+                            let engine = chat.model.resolve(&defaults).engine;
+                            // a continued answer is streamed into the existing message
+                            if message_id == chat.messages.len() {
+                                chat.push(role, String::new());
+                            }
+                            let message = &mut chat.messages[message_id];
+                            message.model = Some(engine);
+                            message.stopped = false;
+                            message.incomplete = true;
                         }
                         ResponseChunk::Content { delta, .. } => {
-                            chat.history[message_id].content += &delta;
+                            let elapsed_ms = chat.request_elapsed_ms();
+                            let message = &mut chat.messages[message_id];
+                            message.content += &delta;
+                            if message.time_to_first_token_ms.is_none() {
+                                message.time_to_first_token_ms = elapsed_ms;
+                            }
+                            // so that not all of it is lost if the app is closed or crashes midway
+                            if app.last_save.map_or(true, |last_save| last_save.elapsed() >= STREAM_SAVE_INTERVAL) {
+                                app.save_state()?;
+                            }
+                        }
+                        ResponseChunk::CloseResponse { finish_reason } => {
+                            chat.messages[message_id].finish_reason = finish_reason;
+                        }
+                        ResponseChunk::Usage { prompt_tokens, completion_tokens } => {
+                            chat.messages[message_id].prompt_tokens = Some(prompt_tokens);
+                            chat.messages[message_id].completion_tokens = Some(completion_tokens);
                         }
                         ResponseChunk::Done => {
+                            chat.messages[message_id].duration_ms = chat.request_elapsed_ms();
+                            chat.messages[message_id].incomplete = false;
+                            chat.streaming = None;
                             app.save_state()?;
 
-                            let chat = app.chat(chat_id).expect("The chat doesn't exist");
-                            // create a title for that chat
-                            if chat.history.len() == 3 {
-                                let mut system_message = "Provide a useful and very descriptive title with max 4 words for the following chat,\
-                                    where System: <text> at the beginning of a line describes what you, the Assistant should be, and
-                                    User: <text> at the beginning of a line denotes what the User asked,\
-                                    and Assistant: <text> at the beginning of a line denotes what your answer is,\
-                                    everything after the following colon is the chat:\n".to_string();
-
-                                for message in &chat.history {
-                                    system_message += &format!("{:?}: {}", message.role, message.content)
-                                }
-
-                                app.chatgpt_message_sender
-                                    .send(ChatGPTMessage::ChatTitleRequest { id: chat.id, system_message })
-                                    .ok();
+                            // create a title for that chat after the first answer
+                            if app.chat(chat_id).map_or(false, |c| c.messages.len() == 3) {
+                                app.request_title(chat_id);
                             }
                         }
-                        // TODO do here anything at all?
-                        ResponseChunk::CloseResponse { .. } => {}
                     }
                 }
             }
@@ -410,21 +1087,45 @@ async fn run_app(mut app: App) -> Result<()> {
                 message_type: ChatGPTMessageChunkType::ChatTitle { chat_id },
                 chunk,
             }) => {
-                if let Some(chat) = app.chat_mut(chat_id) {
+                // the user may have renamed the chat in the meantime
+                if let Some(chat) = app.chat_mut(chat_id).filter(|c| !c.manual_title) {
                     match chunk {
                         ResponseChunk::BeginResponse { .. } => {
-                            chat.title = String::new();
+                            chat.generated_title = Some(String::new());
                         }
                         ResponseChunk::Content { delta, .. } => {
-                            chat.title += &delta;
+                            if let Some(title) = &mut chat.generated_title {
+                                *title += &delta;
+                            }
                         }
                         ResponseChunk::Done => {
-                            app.save_state()?;
+                            if let Some(title) = chat.generated_title.take().filter(|t| !t.trim().is_empty()) {
+                                chat.title = title.trim().to_string();
+                                app.save_state()?;
+                            }
                         }
                         _ => {}
                     }
                 }
             }
+            Some(AppMessage::RequestFailed {
+                message_type: ChatGPTMessageChunkType::Chat { chat_id, message_id },
+                error,
+            }) => {
+                if let Some(chat) = app.chat_mut(chat_id).filter(|c| c.streaming == Some(message_id)) {
+                    chat.error = Some(error);
+                    chat.streaming = None;
+                    app.save_state()?;
+                }
+            }
+            // the chat just keeps its current title
+            Some(AppMessage::RequestFailed {
+                message_type: ChatGPTMessageChunkType::ChatTitle { chat_id }, ..
+            }) => {
+                if let Some(chat) = app.chat_mut(chat_id) {
+                    chat.generated_title = None;
+                }
+            }
             Some(AppMessage::ResizeEvent) => {} // resizes automatically the next time it renders
             None => {
                 app.quit_signal_sender.send(()).ok();
@@ -432,20 +1133,15 @@ async fn run_app(mut app: App) -> Result<()> {
             }
         }
     }
-
-    // cleanup
-    // restore terminal
-    disable_raw_mode()?;
-    execute!(terminal.backend_mut(), LeaveAlternateScreen,)?; // DisableMouseCapture
-    terminal.show_cursor()?;
     Ok(())
 }
 
+/// Forwards the events of the terminal, e.g. [`event::EventStream`], to the app
 async fn handle_input(
+    mut reader: impl futures::Stream<Item = std::io::Result<Event>> + Unpin,
     message_sender: mpsc::UnboundedSender<AppMessage>,
     mut quit_signal_receiver: watch::Receiver<()>,
 ) -> Result<()> {
-    let mut reader = event::EventStream::new();
     loop {
         let event = reader.next().fuse();
 
@@ -474,94 +1170,132 @@ async fn handle_input(
 }
 
 async fn handle_chatgpt(
-    api_key: String,
+    backend: Box<dyn ChatBackend>,
+    // used for everything that isn't a chat request, e.g. title generation
+    mut model: ModelSettings,
     app_message_sender: mpsc::UnboundedSender<AppMessage>,
     mut chat_message_receiver: mpsc::UnboundedReceiver<ChatGPTMessage>,
     mut quit_signal_receiver: watch::Receiver<()>,
 ) -> Result<()> {
-    // Creating a client
-    // TODO support other OS than linux
-    let client =
-        ChatGPT::new_with_config(api_key, ModelConfiguration { engine: ChatGPTEngine::Gpt4, ..Default::default() })?;
-
     let mut open_streams = FuturesUnordered::new();
+    // the chat requests that are currently streamed, so that they can be cancelled,
+    // the request counter makes sure that a finished request doesn't remove a newer one of the same chat
+    let mut chat_streams: HashMap<Uuid, (u64, AbortHandle)> = HashMap::new();
+    // the title requests that are currently streamed, so that they can be stopped when the chat is deleted
+    let mut title_streams: HashMap<Uuid, (u64, AbortHandle)> = HashMap::new();
+    let mut request_counter = 0;
 
     loop {
         select! {
             Ok(()) = quit_signal_receiver.changed() =>  return Ok(()),
             Some(message) = chat_message_receiver.recv() => {
                 match message {
-                    ChatGPTMessage::ChatRequest { id, messages } => {
-                        let message_id = messages.len();
-                        let stream = client.send_history_streaming(&messages).await?;
-                        let app_message_sender = app_message_sender.clone();
-                        let chat_stream = stream.for_each(move |chunk| {
-                            app_message_sender
-                                .send(AppMessage::ChatGPTMessageChunkReceived {
-                                    message_type: ChatGPTMessageChunkType::Chat { chat_id: id, message_id },
-                                    chunk,
-                                })
-                                .ok();
-                            futures::future::ready(())
-                        });
-                        open_streams.push(chat_stream.boxed());
+                    ChatGPTMessage::ChatRequest { id, message_id, messages, model } => {
+                        let message_type = ChatGPTMessageChunkType::Chat { chat_id: id, message_id };
+                        let response = backend.send_history_streaming(messages, &model);
+                        let (abort_handle, abort_registration) = AbortHandle::new_pair();
+                        request_counter += 1;
+                        if let Some((_, previous_request)) = chat_streams.insert(id, (request_counter, abort_handle)) {
+                            previous_request.abort();
+                        }
+                        let stream = stream_response(response, message_type, app_message_sender.clone());
+                        let request = (id, request_counter);
+                        open_streams.push(Abortable::new(stream, abort_registration).map(move |_| request).boxed());
                     }
                     ChatGPTMessage::ChatTitleRequest { id, system_message } => {
                         let message = vec![ChatMessage { role: Role::System, content: system_message }];
-                        let stream = client.send_history_streaming(&message).await?;
-                        let app_message_sender = app_message_sender.clone();
-                        let chat_stream = stream.for_each(move |chunk| {
-                            app_message_sender
-                                .send(AppMessage::ChatGPTMessageChunkReceived {
-                                    message_type: ChatGPTMessageChunkType::ChatTitle { chat_id: id },
-                                    chunk,
-                                })
-                                .ok();
-                            futures::future::ready(())
-                        });
-                        open_streams.push(chat_stream.boxed());
+                        let response = backend.send_history_streaming(message, &model);
+                        let message_type = ChatGPTMessageChunkType::ChatTitle { chat_id: id };
+                        let (abort_handle, abort_registration) = AbortHandle::new_pair();
+                        request_counter += 1;
+                        if let Some((_, previous_request)) = title_streams.insert(id, (request_counter, abort_handle)) {
+                            previous_request.abort();
+                        }
+                        let stream = stream_response(response, message_type, app_message_sender.clone());
+                        let request = (id, request_counter);
+                        open_streams.push(Abortable::new(stream, abort_registration).map(move |_| request).boxed());
+                    }
+                    ChatGPTMessage::CancelRequest { id } => {
+                        if let Some((_, request)) = chat_streams.remove(&id) {
+                            request.abort();
+                        }
+                    }
+                    ChatGPTMessage::DeleteChat { id } => {
+                        for (_, request) in chat_streams.remove(&id).into_iter().chain(title_streams.remove(&id)) {
+                            request.abort();
+                        }
+                    }
+                    // streams that are already open don't depend on the settings, so they just continue
+                    ChatGPTMessage::ChangeModelConfiguration(new_model) => {
+                        model = new_model;
+                    }
+                }
+            },
+            Some((chat_id, request_id)) = open_streams.next() => {
+                for streams in [&mut chat_streams, &mut title_streams] {
+                    if streams.get(&chat_id).map_or(false, |(id, _)| *id == request_id) {
+                        streams.remove(&chat_id);
                     }
-                    // ChatGPTMessage::ChangeModelConfiguration(config) => {
-                    //     client = ChatGPT::new_with_config(key, config)?;
-                    // }
                 }
             },
-            _ = open_streams.next() => {},
+        }
+    }
+}
 
+/// Forwards the chunks of `response` to the app, or the error if the request fails
+fn stream_response(
+    response: BoxFuture<'static, Result<ResponseStream>>,
+    message_type: ChatGPTMessageChunkType,
+    app_message_sender: mpsc::UnboundedSender<AppMessage>,
+) -> BoxFuture<'static, ()> {
+    async move {
+        let result = async {
+            let mut stream = response.await?;
+            while let Some(chunk) = stream.next().await {
+                app_message_sender.send(AppMessage::ChatGPTMessageChunkReceived { message_type, chunk: chunk? }).ok();
+            }
+            anyhow::Ok(())
+        }
+        .await;
+        if let Err(err) = result {
+            app_message_sender
+                .send(AppMessage::RequestFailed { message_type, error: RequestError::from_error(err) })
+                .ok();
         }
     }
+    .boxed()
 }
 
 /// helper function to create a centered rect using up certain percentage of the available rect `r`
-// fn centered_rect(percent_x: u16, percent_y: u16, r: Rect) -> Rect {
-//     let popup_layout = Layout::default()
-//         .direction(Direction::Vertical)
-//         .constraints(
-//             [
-//                 Constraint::Percentage((100 - percent_y) / 2),
-//                 Constraint::Percentage(percent_y),
-//                 Constraint::Percentage((100 - percent_y) / 2),
-//             ]
-//             .as_ref(),
-//         )
-//         .split(r);
-
-//     Layout::default()
-//         .direction(Direction::Horizontal)
-//         .constraints(
-//             [
-//                 Constraint::Percentage((100 - percent_x) / 2),
-//                 Constraint::Percentage(percent_x),
-//                 Constraint::Percentage((100 - percent_x) / 2),
-//             ]
-//             .as_ref(),
-//         )
-//         .split(popup_layout[1])[1]
-// }
+fn centered_rect(percent_x: u16, percent_y: u16, r: Rect) -> Rect {
+    let popup_layout = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints(
+            [
+                Constraint::Percentage((100 - percent_y) / 2),
+                Constraint::Percentage(percent_y),
+                Constraint::Percentage((100 - percent_y) / 2),
+            ]
+            .as_ref(),
+        )
+        .split(r);
+
+    Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints(
+            [
+                Constraint::Percentage((100 - percent_x) / 2),
+                Constraint::Percentage(percent_x),
+                Constraint::Percentage((100 - percent_x) / 2),
+            ]
+            .as_ref(),
+        )
+        .split(popup_layout[1])[1]
+}
 
 fn ui<B: Backend>(f: &mut Frame<B>, app: &mut App) {
     f.render_widget(ratatui::widgets::Clear, f.size()); //this clears out the background
-    let in_chat_mode = matches!(app.ui_mode, UiMode::Chat);
+    let in_chat_mode = matches!(app.ui_mode, UiMode::Chat | UiMode::MessageSelection);
     let mut constraints = Vec::new();
     // chat selection constraint
     if !in_chat_mode {
@@ -595,6 +1329,38 @@ fn ui<B: Backend>(f: &mut Frame<B>, app: &mut App) {
         chat_ui(f, app, chunks[if !in_chat_mode { 1 } else { 0 }]);
     }
 
+    if let Some(model_picker) = &app.model_picker {
+        // termimad would draw over the popup otherwise
+        app.draw_chat_area = None;
+        model_picker_ui(f, model_picker, centered_rect(60, 40, f.size()));
+    }
+
+    if matches!(app.ui_mode, UiMode::Search) {
+        app.draw_chat_area = None;
+        search_ui(f, &app.search, centered_rect(80, 80, f.size()));
+    }
+
+    if let Some(title) = app.delete_confirmation.and_then(|id| app.chat(id)).map(|c| c.title.clone()) {
+        app.draw_chat_area = None;
+        let area = centered_rect(50, 20, f.size());
+        let question = Paragraph::new(format!("Delete \"{title}\"?\n\ny/Enter: delete, n/Esc: keep"))
+            .block(Block::default().borders(Borders::ALL).title("Delete chat"))
+            .wrap(ratatui::widgets::Wrap { trim: true })
+            .alignment(ratatui::layout::Alignment::Center);
+        f.render_widget(ratatui::widgets::Clear, area);
+        f.render_widget(question, area);
+    }
+
+    if !app.state.problems.is_empty() {
+        app.draw_chat_area = None;
+        let area = centered_rect(80, 50, f.size());
+        let problems = Paragraph::new(format!("{}\n\nPress any key to continue", app.state.problems.join("\n\n")))
+            .block(Block::default().borders(Borders::ALL).title("Problems while loading the chats"))
+            .wrap(ratatui::widgets::Wrap { trim: false });
+        f.render_widget(ratatui::widgets::Clear, area);
+        f.render_widget(problems, area);
+    }
+
     // Something like this has its problems because termimad overwrites this at a later step...
     // if matches!(app.ui_mode, UiMode::Help) {
     //     let block =
@@ -606,40 +1372,85 @@ fn ui<B: Backend>(f: &mut Frame<B>, app: &mut App) {
 }
 
 fn chat_selection_ui<B: Backend>(f: &mut Frame<B>, app: &App, area: Rect) {
-    let chat_titles: Vec<ListItem> =
-        app.state.chats.iter().rev().map(|chat| ListItem::new(chat.title.clone())).collect();
+    let rows = app.chat_list_rows();
+    let selected_row = app.selected_chat_list_row(&rows);
+    let section_style = Style::default().fg(app.config.theme.headers.into()).add_modifier(Modifier::BOLD);
+    let matched_style = Style::default().fg(app.config.theme.headers.into());
+    let chat_titles: Vec<ListItem> = rows
+        .iter()
+        .enumerate()
+        .map(|(row, chat_list_row)| match chat_list_row {
+            ChatListRow::Section { tag, count } => {
+                let name = tag.as_ref().map(|t| format!("#{t}")).unwrap_or_else(|| "Untagged".into());
+                ListItem::new(Spans::from(Span::styled(format!("{name} ({count})"), section_style)))
+            }
+            ChatListRow::Chat { idx, .. } if Some(row) == selected_row => {
+                let chat = &app.state.chats[*idx];
+                match app.ui_mode {
+                    UiMode::ChatRename => ListItem::new(format!("{}▏", app.list_input)),
+                    UiMode::ChatTags => ListItem::new(format!("tags: {}▏", app.list_input)),
+                    _ if chat.pinned => ListItem::new(format!("* {}", chat.title)),
+                    _ => ListItem::new(chat.title.clone()),
+                }
+            }
+            ChatListRow::Chat { idx, matched } => {
+                let chat = &app.state.chats[*idx];
+                let mut spans: Vec<_> = chat
+                    .title
+                    .chars()
+                    .enumerate()
+                    .map(|(i, c)| {
+                        if matched.contains(&i) {
+                            Span::styled(c.to_string(), matched_style)
+                        } else {
+                            Span::raw(c.to_string())
+                        }
+                    })
+                    .collect();
+                if chat.pinned {
+                    spans.insert(0, Span::raw("* "));
+                }
+                ListItem::new(Spans::from(spans))
+            }
+        })
+        .collect();
     let mut state = ListState::default();
-    let selected_chat = app.state.current_chat_id.and_then(|current_chat_id| {
-        app.state
-            .chats
-            .iter()
-            .rev()
-            .enumerate()
-            .find_map(|(i, chat)| if chat.id == current_chat_id { Some(i) } else { None })
-    });
-    state.select(selected_chat);
+    state.select(selected_row);
 
+    let chats = if app.show_archived { "Archived chats" } else { "Chats" };
+    let title = match (&app.ui_mode, &app.tag_filter) {
+        _ if app.status.is_some() => format!("{chats} ({})", app.status.as_deref().unwrap_or_default()),
+        (UiMode::ChatRename, _) => format!("{chats} (Enter: rename, Esc: cancel)"),
+        (UiMode::ChatTags, _) => format!("{chats} (comma separated tags, Enter: save, Esc: cancel)"),
+        (UiMode::ChatFilter, _) => format!("Filter: {}▏", app.chat_filter),
+        (_, Some(tag)) => format!("{chats} #{tag} (#: next tag)"),
+        _ if app.show_archived => format!("{chats} (x: restore, v: back)"),
+        _ => format!(
+            "{chats} (f: filter, /: search, r: rename, t: title, a: tags, #: filter tag, p: pin, x: archive, \
+            v: archived, e: export, d: delete)"
+        ),
+    };
     let chats = List::new(chat_titles)
-        .block(Block::default().borders(Borders::ALL).title("Chats"))
+        .block(Block::default().borders(Borders::ALL).title(title))
         .highlight_style(Style::default().add_modifier(Modifier::BOLD))
         .highlight_symbol("> ");
 
     f.render_stateful_widget(chats, area, &mut state);
 }
 
-fn make_skin() -> termimad::MadSkin {
+fn make_skin(theme: &Theme) -> termimad::MadSkin {
     let mut skin = termimad::MadSkin::default();
     skin.table.align = termimad::Alignment::Center;
-    skin.set_headers_fg(termimad::crossterm::style::Color::DarkYellow);
-    skin.bold.set_fg(termimad::crossterm::style::Color::DarkYellow);
-    skin.italic.set_fg(termimad::crossterm::style::Color::DarkMagenta);
-    skin.scrollbar.thumb.set_fg(termimad::crossterm::style::Color::DarkYellow);
+    skin.set_headers_fg(theme.headers.into());
+    skin.bold.set_fg(theme.bold.into());
+    skin.italic.set_fg(theme.italic.into());
+    skin.scrollbar.thumb.set_fg(theme.scrollbar.into());
     skin.code_block.align = termimad::Alignment::Left;
     skin
 }
 
 fn chat_ui<B: Backend>(f: &mut Frame<B>, app: &mut App, area: Rect) {
-    let in_chat_mode = matches!(app.ui_mode, UiMode::Chat);
+    let in_chat_mode = matches!(app.ui_mode, UiMode::Chat | UiMode::MessageSelection);
     let chunks = Layout::default()
         .direction(Direction::Vertical)
         .constraints(if in_chat_mode {
@@ -649,6 +1460,8 @@ fn chat_ui<B: Backend>(f: &mut Frame<B>, app: &mut App, area: Rect) {
         })
         .split(area);
 
+    let selected_message = app.current_chat().and_then(|c| c.selected_message);
+    let editing = app.current_chat().and_then(|c| c.editing);
     if in_chat_mode {
         let wrapped_input = textwrap::wrap(
             app.current_chat().map(|c| c.input.as_str()).unwrap_or(""),
@@ -656,20 +1469,67 @@ fn chat_ui<B: Backend>(f: &mut Frame<B>, app: &mut App, area: Rect) {
         )
         .join("\n");
         let input = Paragraph::new(wrapped_input.as_ref())
-            .style(Style::default().fg(Color::Blue))
-            .block(Block::default().borders(Borders::TOP.union(Borders::BOTTOM)).title("Input"))
+            .style(Style::default().fg(app.config.theme.input.into()))
+            .block(Block::default().borders(Borders::TOP.union(Borders::BOTTOM)).title(if editing.is_some() {
+                "Input (editing, Enter: send as new version, Esc: cancel)"
+            } else {
+                "Input (Ctrl+R: regenerate, Ctrl+←/→: switch version, Ctrl+E: edit, Ctrl+C: stop, Ctrl+P: model)"
+            }))
             .alignment(ratatui::layout::Alignment::Left);
         f.render_widget(input, chunks[1]);
     }
 
-    let messages: String =
-        app.current_chat_idx().map(|i| app.state.chats[i].history.iter()).into_iter().flatten().fold(
-            String::new(),
-            |mut acc, message| {
-                acc += &format!("\n\n## {:?}:\n\n{}", message.role, message.content);
-                acc
-            },
-        );
+    let mut messages = String::new();
+    // where the highlighted message begins, if the view should be scrolled to it
+    let mut highlight_offset = None;
+    if let Some(chat) = app.current_chat_mut() {
+        if std::mem::take(&mut chat.scroll_to_highlight) {
+            highlight_offset = Some(0);
+        }
+    }
+    if let Some(chat) = app.current_chat() {
+        for i in chat.active_branch() {
+            let message = &chat.messages[i];
+            let stopped = if message.stopped {
+                " (stopped)"
+            } else if message.incomplete && chat.streaming != Some(i) {
+                " (incomplete)"
+            } else {
+                ""
+            };
+            let versions = chat.versions(i);
+            let version = match versions.iter().position(|&v| v == i) {
+                Some(position) if versions.len() > 1 => format!(" ({}/{})", position + 1, versions.len()),
+                _ => String::new(),
+            };
+            let selected = if selected_message == Some(i) && message.role == Role::User {
+                " (selected, Enter: edit, ←/→: switch version, Esc: cancel)"
+            } else if selected_message == Some(i) {
+                " (selected, ←/→: switch version, Esc: cancel)"
+            } else if editing == Some(i) {
+                " (editing)"
+            } else {
+                ""
+            };
+            let content = match &chat.highlight {
+                Some((idx, query)) if *idx == i => {
+                    highlight_offset = highlight_offset.map(|_| messages.len());
+                    highlight_matches(&message.content, query)
+                }
+                _ => message.content.clone(),
+            };
+            let info = message_info(message);
+            messages += &format!("\n\n## {:?}{version}{stopped}{selected}:{info}\n\n{content}", message.role);
+        }
+    }
+    if let Some(error) = app.current_chat().and_then(|c| c.error.as_ref()) {
+        messages += &format!("\n\n## Error:\n\n> **{error}**\n\n*Press Ctrl+R to retry*");
+    } else if let Some(chat) = app.current_chat().filter(|c| c.streaming.is_none()) {
+        let head = &chat.messages[chat.head];
+        if head.role == Role::Assistant && (head.incomplete || head.stopped) {
+            messages += "\n\n*Press Ctrl+O to continue the answer*";
+        }
+    }
 
     let (borders, message_area) = if !in_chat_mode {
         (
@@ -690,12 +1550,21 @@ fn chat_ui<B: Backend>(f: &mut Frame<B>, app: &mut App, area: Rect) {
 
     let message_area_border = Block::default()
         .borders(borders)
-        .style(Style::default().bg(Color::Black))
-        .title(app.current_chat().map(|c| c.title.as_str()).unwrap_or("Messages"));
+        .style(Style::default().bg(app.config.theme.chat_background.into()))
+        .title(format!(
+            "{} ({})",
+            app.current_chat().map(|c| c.title.as_str()).unwrap_or("Messages"),
+            app.current_model().engine
+        ));
     f.render_widget(message_area_border, chunks[0]);
 
+    let skin = make_skin(&app.config.theme);
     app.draw_chat_area = Some(Box::new(move |scroll| {
-        let mut view = termimad::MadView::from(messages, message_area, make_skin());
+        let scroll = match highlight_offset {
+            Some(offset) => skin.area_text(&messages[..offset], &message_area).lines.len(),
+            None => scroll,
+        };
+        let mut view = termimad::MadView::from(messages, message_area, skin);
         let mut w = std::io::stdout();
         // view.scroll = scroll;
         view.try_scroll_lines(scroll as i32);
@@ -704,3 +1573,50 @@ fn chat_ui<B: Backend>(f: &mut Frame<B>, app: &mut App, area: Rect) {
         view.scroll
     }));
 }
+
+/// The metadata of the message for its header, e.g. ` *gpt-4 · 2023-05-01 12:00 · 2.1s (first token 0.4s) · 25 + 120 tokens*`
+fn message_info(message: &chat::Message) -> String {
+    let seconds = |ms: u64| format!("{:.1}s", ms as f64 / 1000.0);
+    let mut info = Vec::new();
+    info.extend(message.model.clone());
+    info.extend(message.created_at.map(|t| t.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M").to_string()));
+    match (message.duration_ms, message.time_to_first_token_ms) {
+        (Some(duration), Some(first_token)) => {
+            info.push(format!("{} (first token {})", seconds(duration), seconds(first_token)))
+        }
+        (Some(duration), None) => info.push(seconds(duration)),
+        (None, Some(first_token)) => info.push(format!("first token {}", seconds(first_token))),
+        (None, None) => {}
+    }
+    if let (Some(prompt_tokens), Some(completion_tokens)) = (message.prompt_tokens, message.completion_tokens) {
+        info.push(format!("{prompt_tokens} + {completion_tokens} tokens"));
+    }
+    // "stop" is the normal case
+    info.extend(message.finish_reason.clone().filter(|reason| reason != "stop"));
+    if info.is_empty() {
+        String::new()
+    } else {
+        format!(" *{}*", info.join(" · "))
+    }
+}
+
+/// Makes every occurrence of `query` in the markdown `content` bold, code blocks are left as they are
+fn highlight_matches(content: &str, query: &str) -> String {
+    let mut in_code_block = false;
+    let lines = content.split('\n').map(|line| {
+        if line.trim_start().starts_with("```") {
+            in_code_block = !in_code_block;
+        }
+        if in_code_block {
+            return line.to_string();
+        }
+        let mut highlighted = String::new();
+        let mut rest = line;
+        while let Some(range) = find_ignore_case(rest, query) {
+            highlighted += &format!("{}**{}**", &rest[..range.start], &rest[range.clone()]);
+            rest = &rest[range.end..];
+        }
+        highlighted + rest
+    });
+    lines.collect::<Vec<_>>().join("\n")
+}
@@ -0,0 +1,175 @@
+//! A small local stand-in for the OpenAI `/v1/chat/completions` endpoint, used by the `mock-server` binary and the tests.
+//!
+//! Every request is answered by echoing the last user message, streamed word by word.
+//! Error responses can be provoked with the content of the last user message:
+//!
+//! - `/error <status>` answers with the given HTTP status and an OpenAI style error body
+//! - `/error stream` starts streaming and then sends a malformed chunk
+
+use std::{convert::Infallible, net::SocketAddr, time::Duration};
+
+use futures::{future::BoxFuture, FutureExt};
+use hyper::{
+    body::Bytes,
+    header::CONTENT_TYPE,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use serde::Deserialize;
+use serde_json::json;
+
+#[derive(Debug, Clone, clap::Args)]
+pub struct Settings {
+    /// Delay between two streamed chunks in milliseconds
+    #[arg(long, default_value = "30")]
+    pub chunk_delay_ms: u64,
+    /// Answer every request with this HTTP status (e.g. 429) instead
+    #[arg(long)]
+    pub fail_with: Option<u16>,
+}
+
+#[derive(Deserialize)]
+struct CompletionRequest {
+    model: String,
+    messages: Vec<Message>,
+    #[serde(default)]
+    stream: bool,
+    stream_options: Option<StreamOptions>,
+}
+
+#[derive(Deserialize)]
+struct StreamOptions {
+    #[serde(default)]
+    include_usage: bool,
+}
+
+#[derive(Deserialize)]
+struct Message {
+    role: String,
+    content: String,
+}
+
+/// Starts listening on `addr`, the returned address is the one actually used, e.g. when the port of `addr` is 0.
+/// The server runs until the returned future is dropped
+pub fn serve(
+    addr: SocketAddr,
+    settings: Settings,
+) -> hyper::Result<(SocketAddr, BoxFuture<'static, hyper::Result<()>>)> {
+    let make_service = make_service_fn(move |_| {
+        let settings = settings.clone();
+        async move { Ok::<_, Infallible>(service_fn(move |request| handle(settings.clone(), request))) }
+    });
+    let server = Server::try_bind(&addr)?.serve(make_service);
+    Ok((server.local_addr(), server.boxed()))
+}
+
+async fn handle(settings: Settings, request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    if request.method() != Method::POST || !request.uri().path().ends_with("/chat/completions") {
+        return Ok(error_response(StatusCode::NOT_FOUND, "Unknown endpoint"));
+    }
+    if let Some(status) = settings.fail_with {
+        return Ok(error_response(
+            StatusCode::from_u16(status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR),
+            "Mock error",
+        ));
+    }
+
+    let body = match hyper::body::to_bytes(request.into_body()).await {
+        Ok(body) => body,
+        Err(err) => return Ok(error_response(StatusCode::BAD_REQUEST, &err.to_string())),
+    };
+    let request: CompletionRequest = match serde_json::from_slice(&body) {
+        Ok(request) => request,
+        Err(err) => return Ok(error_response(StatusCode::BAD_REQUEST, &format!("Invalid request: {err}"))),
+    };
+
+    let last_user_message =
+        request.messages.iter().rev().find(|m| m.role == "user").map(|m| m.content.trim()).unwrap_or("");
+    let mut fail_stream = false;
+    if let Some(error) = last_user_message.strip_prefix("/error") {
+        match error.trim() {
+            "stream" => fail_stream = true,
+            status => {
+                let status = status.parse().ok().and_then(|s| StatusCode::from_u16(s).ok());
+                let status = status.unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+                return Ok(error_response(status, &format!("Requested error {status}")));
+            }
+        }
+    }
+
+    let answer =
+        if last_user_message.is_empty() { "Mock response".into() } else { format!("You said: {last_user_message}") };
+    // words instead of tokens, but it's close enough for testing
+    let prompt_tokens: usize = request.messages.iter().map(|m| m.content.split_whitespace().count()).sum();
+    let completion_tokens = answer.split_whitespace().count();
+    let usage = json!({
+        "prompt_tokens": prompt_tokens,
+        "completion_tokens": completion_tokens,
+        "total_tokens": prompt_tokens + completion_tokens,
+    });
+    let include_usage = request.stream_options.as_ref().map_or(false, |o| o.include_usage);
+
+    if !request.stream {
+        let body = json!({
+            "id": "chatcmpl-mock",
+            "object": "chat.completion",
+            "created": chrono::Utc::now().timestamp(),
+            "model": request.model,
+            "usage": usage,
+            "choices": [{ "index": 0, "message": { "role": "assistant", "content": answer }, "finish_reason": "stop" }],
+        });
+        return Ok(Response::builder().header(CONTENT_TYPE, "application/json").body(body.to_string().into()).unwrap());
+    }
+
+    let (mut sender, body) = Body::channel();
+    let chunk_delay = Duration::from_millis(settings.chunk_delay_ms);
+    tokio::spawn(async move {
+        let chunk = |delta: serde_json::Value, finish_reason: Option<&str>| {
+            let chunk = json!({
+                "id": "chatcmpl-mock",
+                "object": "chat.completion.chunk",
+                "created": chrono::Utc::now().timestamp(),
+                "model": request.model,
+                "choices": [{ "index": 0, "delta": delta, "finish_reason": finish_reason }],
+            });
+            Bytes::from(format!("data: {chunk}\n\n"))
+        };
+
+        let mut events = vec![chunk(json!({ "role": "assistant" }), None)];
+        let words: Vec<_> = answer.split_inclusive(char::is_whitespace).collect();
+        if fail_stream {
+            events.extend(words.iter().take(3).map(|word| chunk(json!({ "content": word }), None)));
+            events.push(Bytes::from("data: {\"this is\": not json\n\n"));
+        } else {
+            events.extend(words.iter().map(|word| chunk(json!({ "content": word }), None)));
+            events.push(chunk(json!({}), Some("stop")));
+            if include_usage {
+                let chunk = json!({
+                    "id": "chatcmpl-mock",
+                    "object": "chat.completion.chunk",
+                    "created": chrono::Utc::now().timestamp(),
+                    "model": request.model,
+                    "choices": [],
+                    "usage": usage,
+                });
+                events.push(Bytes::from(format!("data: {chunk}\n\n")));
+            }
+            events.push(Bytes::from("data: [DONE]\n\n"));
+        }
+
+        for event in events {
+            tokio::time::sleep(chunk_delay).await;
+            // the client disconnected
+            if sender.send_data(event).await.is_err() {
+                return;
+            }
+        }
+    });
+
+    Ok(Response::builder().header(CONTENT_TYPE, "text/event-stream").body(body).unwrap())
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    let body = json!({ "error": { "message": message, "type": "mock_error", "code": status.as_u16() } });
+    Response::builder().status(status).header(CONTENT_TYPE, "application/json").body(body.to_string().into()).unwrap()
+}
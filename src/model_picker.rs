@@ -0,0 +1,138 @@
+use crossterm::event::KeyCode;
+use ratatui::{
+    backend::Backend,
+    layout::Rect,
+    style::{Modifier, Style},
+    widgets::{Block, Borders, Clear, List, ListItem, ListState},
+    Frame,
+};
+use uuid::Uuid;
+
+use crate::config::ModelSettings;
+
+/// Models that can always be selected by cycling through them in the picker
+const ENGINES: &[&str] = &["gpt-3.5-turbo", "gpt-3.5-turbo-0301", "gpt-4", "gpt-4-0314", "gpt-4-32k", "gpt-4-32k-0314"];
+
+const FIELD_COUNT: usize = 6;
+
+pub enum ModelPickerEvent {
+    None,
+    Apply(ModelSettings),
+    Cancel,
+}
+
+/// Popup to change the model and its sampling parameters,
+/// Up/Down select a parameter, Left/Right change it, Enter applies and Esc discards the changes
+pub struct ModelPicker {
+    /// The chat whose settings are changed, or the global settings when `None`
+    pub chat_id: Option<Uuid>,
+    settings: ModelSettings,
+    selected: usize,
+    /// [`ENGINES`] with the current engine and the engines of the config in front, if they aren't in it
+    engines: Vec<String>,
+}
+
+impl ModelPicker {
+    /// `configured_engines` are offered besides [`ENGINES`], e.g. the custom models of the profiles
+    pub fn new(chat_id: Option<Uuid>, settings: ModelSettings, configured_engines: Vec<String>) -> Self {
+        let custom = std::iter::once(settings.engine.clone())
+            .chain(configured_engines)
+            .filter(|engine| !ENGINES.contains(&engine.as_str()));
+        let mut engines: Vec<String> = Vec::new();
+        for engine in custom.chain(ENGINES.iter().map(|engine| engine.to_string())) {
+            if !engines.contains(&engine) {
+                engines.push(engine);
+            }
+        }
+        ModelPicker { chat_id, settings, selected: 0, engines }
+    }
+
+    pub fn handle_key(&mut self, key: KeyCode) -> ModelPickerEvent {
+        match key {
+            KeyCode::Up => self.selected = (self.selected + FIELD_COUNT - 1) % FIELD_COUNT,
+            KeyCode::Down | KeyCode::Tab => self.selected = (self.selected + 1) % FIELD_COUNT,
+            KeyCode::Left | KeyCode::Char('h') | KeyCode::Char('-') => self.change(-1),
+            KeyCode::Right | KeyCode::Char('l') | KeyCode::Char('+') => self.change(1),
+            KeyCode::Enter => return ModelPickerEvent::Apply(self.settings.clone()),
+            KeyCode::Esc | KeyCode::Char('q') => return ModelPickerEvent::Cancel,
+            _ => {}
+        }
+        ModelPickerEvent::None
+    }
+
+    fn change(&mut self, direction: i32) {
+        let step = |value: &mut f32, step: f32, min: f32, max: f32| {
+            *value = ((*value + step * direction as f32).clamp(min, max) * 100.0).round() / 100.0;
+        };
+        match self.selected {
+            0 => {
+                let idx = self.engines.iter().position(|e| *e == self.settings.engine).unwrap_or_default() as i32;
+                let new_idx = (idx + direction).rem_euclid(self.engines.len() as i32);
+                self.settings.engine = self.engines[new_idx as usize].clone();
+            }
+            1 => step(&mut self.settings.temperature, 0.1, 0.0, 2.0),
+            2 => step(&mut self.settings.top_p, 0.05, 0.0, 1.0),
+            3 => step(&mut self.settings.presence_penalty, 0.1, -2.0, 2.0),
+            4 => step(&mut self.settings.frequency_penalty, 0.1, -2.0, 2.0),
+            _ => {
+                // 0 means unlimited
+                let max_tokens = self.settings.max_tokens.unwrap_or(0) as i32 + 256 * direction;
+                self.settings.max_tokens = (max_tokens > 0).then_some(max_tokens as u32);
+            }
+        }
+    }
+}
+
+pub fn model_picker_ui<B: Backend>(f: &mut Frame<B>, picker: &ModelPicker, area: Rect) {
+    let settings = &picker.settings;
+    let items: Vec<ListItem> = [
+        format!("Model:             < {} >", settings.engine),
+        format!("Temperature:       < {:.2} >", settings.temperature),
+        format!("Top p:             < {:.2} >", settings.top_p),
+        format!("Presence penalty:  < {:.2} >", settings.presence_penalty),
+        format!("Frequency penalty: < {:.2} >", settings.frequency_penalty),
+        format!(
+            "Max tokens:        < {} >",
+            settings.max_tokens.map(|t| t.to_string()).unwrap_or_else(|| "unlimited".into())
+        ),
+    ]
+    .into_iter()
+    .map(ListItem::new)
+    .collect();
+    let mut state = ListState::default();
+    state.select(Some(picker.selected));
+
+    let list = List::new(items)
+        .block(Block::default().borders(Borders::ALL).title(if picker.chat_id.is_some() {
+            "Model of this chat (Enter: apply, Esc: cancel)"
+        } else {
+            "Default model (Enter: apply, Esc: cancel)"
+        }))
+        .highlight_style(Style::default().add_modifier(Modifier::BOLD))
+        .highlight_symbol("> ");
+
+    f.render_widget(Clear, area);
+    f.render_stateful_widget(list, area, &mut state);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn engine(picker: &mut ModelPicker, key: KeyCode) -> String {
+        picker.handle_key(key);
+        picker.settings.engine.clone()
+    }
+
+    #[test]
+    fn cycles_through_the_configured_engines() {
+        let settings = ModelSettings { engine: "llama-3".into(), ..ModelSettings::default() };
+        let mut picker = ModelPicker::new(None, settings, vec!["gpt-4".into(), "llama-3".into(), "mistral".into()]);
+        assert_eq!(engine(&mut picker, KeyCode::Right), "mistral");
+        assert_eq!(engine(&mut picker, KeyCode::Right), ENGINES[0]);
+        assert_eq!(engine(&mut picker, KeyCode::Left), "mistral");
+        assert_eq!(engine(&mut picker, KeyCode::Left), "llama-3");
+        // wraps around
+        assert_eq!(engine(&mut picker, KeyCode::Left), ENGINES[ENGINES.len() - 1]);
+    }
+}
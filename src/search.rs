@@ -0,0 +1,254 @@
+use std::ops::Range;
+
+use crossterm::event::KeyCode;
+use ratatui::{
+    backend::Backend,
+    layout::{Constraint, Direction, Rect},
+    style::{Modifier, Style},
+    text::{Span, Spans},
+    widgets::{Block, Borders, Clear, List, ListItem, ListState, Paragraph},
+    Frame,
+};
+use uuid::Uuid;
+
+use crate::chat::Chat;
+
+/// How many characters of a message are shown before and after a match
+const SNIPPET_CONTEXT: usize = 40;
+
+const MAX_RESULTS: usize = 200;
+
+pub enum SearchEvent {
+    None,
+    /// Jump to the message that contains the match
+    Open {
+        chat_id: Uuid,
+        message_idx: usize,
+    },
+    Cancel,
+}
+
+/// A message that contains the query, with the text around the first match
+pub struct SearchResult {
+    chat_id: Uuid,
+    message_idx: usize,
+    title: String,
+    before: String,
+    matched: String,
+    after: String,
+}
+
+/// Full-text search over the messages of all chats (including inactive versions),
+/// typing changes the query, Up/Down select a result, Enter opens it and Esc goes back to the chat list
+#[derive(Default)]
+pub struct Search {
+    query: String,
+    results: Vec<SearchResult>,
+    selected: usize,
+}
+
+impl Search {
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    pub fn handle_key(&mut self, key: KeyCode, chats: &[Chat]) -> SearchEvent {
+        match key {
+            KeyCode::Up => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Down => self.selected = (self.selected + 1).min(self.results.len().saturating_sub(1)),
+            KeyCode::Enter => {
+                if let Some(result) = self.results.get(self.selected) {
+                    return SearchEvent::Open { chat_id: result.chat_id, message_idx: result.message_idx };
+                }
+            }
+            KeyCode::Esc => return SearchEvent::Cancel,
+            KeyCode::Char(c) => {
+                self.query.push(c);
+                self.update(chats);
+            }
+            KeyCode::Backspace => {
+                self.query.pop();
+                self.update(chats);
+            }
+            _ => {}
+        }
+        SearchEvent::None
+    }
+
+    /// Searches again, e.g. because the chats changed since the last search
+    pub fn update(&mut self, chats: &[Chat]) {
+        self.selected = 0;
+        self.results.clear();
+        if self.query.trim().is_empty() {
+            return;
+        }
+        // newest chats first, like in the chat list
+        let messages = chats.iter().rev().flat_map(|chat| chat.messages.iter().enumerate().map(move |m| (chat, m)));
+        for (chat, (message_idx, message)) in messages {
+            if let Some(range) = find_ignore_case(&message.content, &self.query) {
+                let content = &message.content;
+                let before: Vec<_> = content[..range.start].chars().rev().take(SNIPPET_CONTEXT + 1).collect();
+                let before: String = before.into_iter().rev().collect();
+                let after: String = content[range.end..].chars().take(SNIPPET_CONTEXT + 1).collect();
+                self.results.push(SearchResult {
+                    chat_id: chat.id,
+                    message_idx,
+                    title: if chat.archived { format!("{} (archived)", chat.title) } else { chat.title.clone() },
+                    before: shorten(&before, true),
+                    matched: content[range].replace('\n', " "),
+                    after: shorten(&after, false),
+                });
+                if self.results.len() == MAX_RESULTS {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Puts the snippet on one line and marks it with "…" if it's cut off
+fn shorten(text: &str, at_start: bool) -> String {
+    let text = text.replace('\n', " ");
+    if text.chars().count() <= SNIPPET_CONTEXT {
+        return text;
+    }
+    if at_start {
+        format!("…{}", text.chars().skip(1).collect::<String>())
+    } else {
+        format!("{}…", text.chars().take(SNIPPET_CONTEXT).collect::<String>())
+    }
+}
+
+/// The byte range of the first occurrence of `query` in `text`, ignoring case
+pub fn find_ignore_case(text: &str, query: &str) -> Option<Range<usize>> {
+    if query.is_empty() {
+        return None;
+    }
+    text.char_indices().find_map(|(start, _)| {
+        let mut end = start;
+        let mut chars = text[start..].chars();
+        for q in query.chars() {
+            let c = chars.next()?;
+            if !c.to_lowercase().eq(q.to_lowercase()) {
+                return None;
+            }
+            end += c.len_utf8();
+        }
+        Some(start..end)
+    })
+}
+
+pub fn search_ui<B: Backend>(f: &mut Frame<B>, search: &Search, area: Rect) {
+    let chunks = ratatui::layout::Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Length(3), Constraint::Min(1)])
+        .split(area);
+
+    let input = Paragraph::new(format!("{}▏", search.query))
+        .block(Block::default().borders(Borders::ALL).title("Search (Enter: open, Esc: back)"));
+
+    let bold = Style::default().add_modifier(Modifier::BOLD);
+    let items: Vec<ListItem> = search
+        .results
+        .iter()
+        .map(|result| {
+            ListItem::new(vec![
+                Spans::from(Span::styled(result.title.clone(), bold)),
+                Spans::from(vec![
+                    Span::raw(result.before.clone()),
+                    Span::styled(result.matched.clone(), Style::default().add_modifier(Modifier::REVERSED)),
+                    Span::raw(result.after.clone()),
+                ]),
+            ])
+        })
+        .collect();
+    let mut state = ListState::default();
+    state.select((!search.results.is_empty()).then_some(search.selected));
+
+    let title = match search.results.len() {
+        MAX_RESULTS => format!("Results (first {MAX_RESULTS})"),
+        count => format!("Results ({count})"),
+    };
+    let list = List::new(items).block(Block::default().borders(Borders::ALL).title(title)).highlight_symbol("> ");
+
+    f.render_widget(Clear, area);
+    f.render_widget(input, chunks[0]);
+    f.render_stateful_widget(list, chunks[1], &mut state);
+}
+
+/// Matches `pattern` as a subsequence of `text` ignoring case, like fzf,
+/// returns a score (higher is better) and the char positions in `text` that matched
+pub fn fuzzy_match(text: &str, pattern: &str) -> Option<(i64, Vec<usize>)> {
+    let mut pattern = pattern.chars().filter(|c| !c.is_whitespace()).peekable();
+    let mut score = 0;
+    let mut positions = Vec::new();
+    let mut previous: Option<char> = None;
+    let mut gap = 0;
+    for (i, c) in text.chars().enumerate() {
+        let Some(p) = pattern.peek() else {
+            break;
+        };
+        if c.to_lowercase().eq(p.to_lowercase()) {
+            score += 1;
+            // consecutive matches and matches at the beginning of words are what the user most likely means
+            if positions.last().map_or(false, |&last| last + 1 == i) {
+                score += 5;
+            }
+            if previous.map_or(true, |previous| !previous.is_alphanumeric()) {
+                score += 3;
+            }
+            score -= gap.min(3);
+            gap = 0;
+            positions.push(i);
+            pattern.next();
+        } else if !positions.is_empty() {
+            gap += 1;
+        }
+        previous = Some(c);
+    }
+    pattern.peek().is_none().then_some((score, positions))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_the_byte_range_ignoring_case() {
+        assert_eq!(find_ignore_case("Rust lifetimes", "LIFE"), Some(5..9));
+        assert_eq!(find_ignore_case("Rust lifetimes", "borrow"), None);
+        assert_eq!(find_ignore_case("Rust", ""), None);
+        // the query can't reach past the end of the text
+        assert_eq!(find_ignore_case("Rust", "rusty"), None);
+    }
+
+    #[test]
+    fn finds_non_ascii_text_ignoring_case() {
+        let text = "Ärger über Öl und ΣΟΦΊΑ";
+        let range = find_ignore_case(text, "öL").unwrap();
+        assert_eq!(&text[range], "Öl");
+        let range = find_ignore_case(text, "ÜBER").unwrap();
+        assert_eq!(&text[range], "über");
+        let range = find_ignore_case(text, "σοφία").unwrap();
+        assert_eq!(&text[range], "ΣΟΦΊΑ");
+        assert_eq!(find_ignore_case(text, "ärger"), Some(0.."Ärger".len()));
+    }
+
+    #[test]
+    fn matches_a_subsequence_ignoring_case() {
+        let (_, positions) = fuzzy_match("Rust lifetimes", "rl").unwrap();
+        assert_eq!(positions, [0, 5]);
+        assert_eq!(fuzzy_match("Rust lifetimes", "lr"), None);
+        // whitespace in the pattern is ignored, the positions count chars, not bytes
+        let (_, positions) = fuzzy_match("Ärger über Öl", "ÄR ÜB").unwrap();
+        assert_eq!(positions, [0, 1, 6, 7]);
+    }
+
+    #[test]
+    fn prefers_consecutive_matches_and_word_starts() {
+        let score = |text| fuzzy_match(text, "ab").unwrap().0;
+        assert!(score("ab") > score("a b"));
+        assert!(score("x ab") > score("xab"));
+        assert!(score("a b") > score("a    b"));
+    }
+}
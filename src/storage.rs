@@ -0,0 +1,76 @@
+mod backups;
+mod files;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+use std::{
+    collections::hash_map::DefaultHasher,
+    fmt,
+    hash::{Hash, Hasher},
+    path::PathBuf,
+};
+
+use anyhow::Result;
+
+use crate::{
+    chat::State,
+    config::{Config, StorageKind},
+};
+
+#[cfg(feature = "sqlite")]
+pub use self::sqlite::SqliteStorage;
+pub use self::{backups::Backups, files::FileStorage};
+
+/// Where the chats are persisted
+pub trait Storage: Send {
+    /// Loads all chats, chats that were saved by older versions are converted
+    fn load(&mut self) -> Result<State>;
+
+    /// Persists what changed since the last load or save
+    fn save(&mut self, state: &State) -> Result<()>;
+}
+
+/// The chats were written by a newer version of chatgpt-tui, this version never changes them,
+/// so nothing is lost when an older version is started by accident
+#[derive(Debug)]
+pub struct NewerVersion {
+    pub path: PathBuf,
+    /// What is versioned, e.g. "format" or "schema"
+    pub kind: &'static str,
+    pub version: u32,
+    pub supported: u32,
+}
+
+impl fmt::Display for NewerVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} was written by a newer version of chatgpt-tui ({} version {}, this version supports {})",
+            self.path.display(),
+            self.kind,
+            self.version,
+            self.supported
+        )
+    }
+}
+
+impl std::error::Error for NewerVersion {}
+
+/// Creates the storage that is configured with `storage`, in `paths.state_dir`
+pub fn from_config(config: &Config) -> Result<Box<dyn Storage>> {
+    let state_dir = config.paths.state_dir()?;
+    Ok(match config.storage {
+        StorageKind::Files => Box::new(FileStorage::new(state_dir)),
+        #[cfg(feature = "sqlite")]
+        StorageKind::Sqlite => Box::new(SqliteStorage::open(&state_dir)?),
+        #[cfg(not(feature = "sqlite"))]
+        StorageKind::Sqlite => anyhow::bail!("`storage = \"sqlite\"` needs a build with the `sqlite` feature"),
+    })
+}
+
+/// Used to find out whether something changed since it was saved
+fn hash(content: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    content.hash(&mut hasher);
+    hasher.finish()
+}
@@ -0,0 +1,266 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone};
+use serde::Serialize;
+use uuid::Uuid;
+
+use super::{
+    files::{check_version, migrate, write_atomically, VERSION},
+    Storage,
+};
+use crate::{
+    chat::{Chat, State},
+    config::BackupSettings,
+};
+
+const PREFIX: &str = "state-";
+/// With microseconds, so backups that are taken within the same second don't replace each other
+const TIMESTAMP_FORMAT: &str = "%Y%m%d-%H%M%S%.6f";
+/// Also reads the names of the backups without fractional seconds
+const PARSE_TIMESTAMP_FORMAT: &str = "%Y%m%d-%H%M%S%.f";
+
+/// Snapshots of all chats in `backups/` of the state directory, independent of the storage,
+/// only the newest [`BackupSettings::count`] are kept
+pub struct Backups {
+    dir: PathBuf,
+    settings: BackupSettings,
+    last_backup: Option<Instant>,
+}
+
+/// The content of a backup file, the same format as a chat file but with all chats
+#[derive(Serialize)]
+struct Snapshot<'a> {
+    version: u32,
+    current_chat_id: Option<Uuid>,
+    chats: &'a [Chat],
+}
+
+/// A storage that also takes the periodic backups when it saves
+struct BackedUpStorage {
+    storage: Box<dyn Storage>,
+    backups: Backups,
+}
+
+impl Storage for BackedUpStorage {
+    fn load(&mut self) -> Result<State> {
+        self.storage.load()
+    }
+
+    fn save(&mut self, state: &State) -> Result<()> {
+        self.storage.save(state)?;
+        self.backups.create_if_due(state)
+    }
+}
+
+pub struct Backup {
+    pub path: PathBuf,
+    pub created_at: DateTime<Local>,
+}
+
+impl Backups {
+    pub fn new(state_dir: &Path, settings: BackupSettings) -> Self {
+        Backups { dir: state_dir.join("backups"), settings, last_backup: None }
+    }
+
+    /// Wraps `storage`, so that it takes a backup whenever it saves and the last one is older than the interval
+    pub fn with_storage(self, storage: Box<dyn Storage>) -> Box<dyn Storage> {
+        Box::new(BackedUpStorage { storage, backups: self })
+    }
+
+    /// Takes a backup unless the last one is more recent than [`BackupSettings::interval_minutes`]
+    pub fn create_if_due(&mut self, state: &State) -> Result<()> {
+        let interval = Duration::from_secs(self.settings.interval_minutes * 60);
+        if self.last_backup.map_or(true, |last| last.elapsed() >= interval) {
+            self.create(state)?;
+        }
+        Ok(())
+    }
+
+    /// Writes a snapshot of `state` and removes the oldest ones, returns where it was written.
+    /// Nothing is written if there are no chats or nothing changed since the newest backup
+    pub fn create(&mut self, state: &State) -> Result<Option<PathBuf>> {
+        self.last_backup = Some(Instant::now());
+        if self.settings.count == 0 || state.chats.is_empty() {
+            return Ok(None);
+        }
+        let snapshot = Snapshot { version: VERSION, current_chat_id: state.current_chat_id, chats: &state.chats };
+        let content = toml::to_string_pretty(&snapshot)?;
+        if let Some(newest) = self.list()?.first() {
+            if std::fs::read_to_string(&newest.path).map_or(false, |newest| newest == content) {
+                return Ok(None);
+            }
+        }
+
+        std::fs::create_dir_all(&self.dir).with_context(|| format!("Couldn't create {}", self.dir.display()))?;
+        let path = self.dir.join(format!("{PREFIX}{}.toml", Local::now().format(TIMESTAMP_FORMAT)));
+        write_atomically(&path, &content)?;
+
+        for old in self.list()?.iter().skip(self.settings.count) {
+            std::fs::remove_file(&old.path).with_context(|| format!("Couldn't remove {}", old.path.display()))?;
+        }
+        Ok(Some(path))
+    }
+
+    /// The backups, the newest first
+    pub fn list(&self) -> Result<Vec<Backup>> {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err).with_context(|| format!("Couldn't read {}", self.dir.display())),
+        };
+        let mut backups = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            let Some(timestamp) =
+                path.file_name().and_then(|n| n.to_str()?.strip_prefix(PREFIX)?.strip_suffix(".toml"))
+            else {
+                continue;
+            };
+            let created_at = NaiveDateTime::parse_from_str(timestamp, PARSE_TIMESTAMP_FORMAT)
+                .ok()
+                .and_then(|t| Local.from_local_datetime(&t).earliest());
+            if let Some(created_at) = created_at {
+                backups.push(Backup { path, created_at });
+            }
+        }
+        backups.sort_by_key(|b| std::cmp::Reverse(b.created_at));
+        Ok(backups)
+    }
+
+    /// Finds a backup by its number in [`Backups::list`] (starting at 1) or its file name
+    pub fn find(&self, query: &str) -> Result<Backup> {
+        let backups = self.list()?;
+        let position = match query.parse::<usize>() {
+            Ok(number) => number.checked_sub(1),
+            Err(_) => backups.iter().position(|b| b.path.file_name().map_or(false, |n| n.to_string_lossy() == query)),
+        };
+        match position.and_then(|p| (p < backups.len()).then_some(p)) {
+            Some(position) => Ok(backups.into_iter().nth(position).unwrap()),
+            None => bail!("There's no backup '{query}', `restore` without arguments lists them"),
+        }
+    }
+}
+
+impl Backup {
+    /// Reads the chats of the backup, converting them if they were written by an older version
+    pub fn read(&self) -> Result<State> {
+        let content =
+            std::fs::read_to_string(&self.path).with_context(|| format!("Couldn't read {}", self.path.display()))?;
+        let mut snapshot: toml::Table =
+            toml::from_str(&content).with_context(|| format!("{} isn't a backup", self.path.display()))?;
+        let version = snapshot.remove("version").map(|v| v.try_into()).transpose()?.unwrap_or_default();
+        check_version(&self.path, version)?;
+        let current_chat_id = snapshot.remove("current_chat_id").map(|id| id.try_into()).transpose()?;
+        let chats: Vec<toml::Value> = snapshot.remove("chats").map(|c| c.try_into()).transpose()?.unwrap_or_default();
+        let chats = chats.into_iter().map(|chat| migrate(chat, version)).collect::<Result<_>>()?;
+        Ok(State { chats, current_chat_id, problems: Vec::new() })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::TempDir;
+
+    fn backups(dir: &Path, count: usize) -> Backups {
+        Backups::new(dir, BackupSettings { count, interval_minutes: 60 })
+    }
+
+    fn state(titles: &[&str]) -> State {
+        let chats: Vec<_> = titles
+            .iter()
+            .map(|title| {
+                let mut chat = Chat::new("You are a test".into());
+                chat.title = title.to_string();
+                chat
+            })
+            .collect();
+        State { current_chat_id: chats.first().map(|c| c.id), chats, problems: Vec::new() }
+    }
+
+    fn titles(backup: &Backup) -> Vec<String> {
+        backup.read().unwrap().chats.into_iter().map(|c| c.title).collect()
+    }
+
+    #[test]
+    fn keeps_only_the_newest_backups() {
+        let dir = TempDir::new();
+        let mut backups = backups(&dir, 2);
+        for title in ["First", "Second", "Third"] {
+            assert!(backups.create(&state(&[title])).unwrap().is_some());
+        }
+
+        let list = backups.list().unwrap();
+        assert_eq!(list.len(), 2);
+        assert_eq!(titles(&list[0]), ["Third"]);
+        assert_eq!(titles(&list[1]), ["Second"]);
+    }
+
+    #[test]
+    fn skips_a_backup_that_is_the_same_as_the_newest() {
+        let dir = TempDir::new();
+        let mut backups = backups(&dir, 10);
+        let state = state(&["First"]);
+        assert!(backups.create(&state).unwrap().is_some());
+        assert_eq!(backups.create(&state).unwrap(), None);
+        assert_eq!(backups.create(&State::default()).unwrap(), None);
+        assert_eq!(backups.list().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn finds_a_backup_by_its_number_or_file_name() {
+        let dir = TempDir::new();
+        let mut backups = backups(&dir, 10);
+        backups.create(&state(&["Older"])).unwrap();
+        let newer = backups.create(&state(&["Newer"])).unwrap().unwrap();
+
+        assert_eq!(titles(&backups.find("1").unwrap()), ["Newer"]);
+        assert_eq!(titles(&backups.find("2").unwrap()), ["Older"]);
+        let file_name = newer.file_name().unwrap().to_str().unwrap();
+        assert_eq!(backups.find(file_name).unwrap().path, newer);
+        for query in ["0", "3", "state-19700101-000000.toml"] {
+            assert!(backups.find(query).is_err(), "{query}");
+        }
+    }
+
+    #[test]
+    fn reads_a_backup_of_an_older_version() {
+        let dir = TempDir::new();
+        std::fs::create_dir(dir.join("backups")).unwrap();
+        // before the message tree, without a version and with a file name without fractional seconds
+        std::fs::write(
+            dir.join("backups").join("state-20230501-120000.toml"),
+            r#"current_chat_id = "6f1b3c2e-8f57-4d0c-9a53-1f0c5a3e7b21"
+
+[[chats]]
+title = "Flat"
+input = ""
+input_pos = 0
+scroll = 0
+id = "6f1b3c2e-8f57-4d0c-9a53-1f0c5a3e7b21"
+
+[[chats.history]]
+role = "system"
+content = "You are a test"
+
+[[chats.history]]
+role = "user"
+content = "Hello"
+"#,
+        )
+        .unwrap();
+
+        let backup = backups(&dir, 10).find("1").unwrap();
+        assert_eq!(backup.created_at.format("%Y-%m-%d %H:%M:%S").to_string(), "2023-05-01 12:00:00");
+        let state = backup.read().unwrap();
+        let chat = &state.chats[0];
+        assert_eq!(state.current_chat_id, Some(chat.id));
+        let messages: Vec<_> = chat.messages.iter().map(|m| (m.content.as_str(), m.parent)).collect();
+        assert_eq!(messages, [("You are a test", None), ("Hello", Some(0))]);
+        assert_eq!(chat.head, 1);
+    }
+}
@@ -0,0 +1,532 @@
+use std::{
+    collections::HashMap,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::{hash, NewerVersion, Storage};
+use crate::chat::{Chat, State};
+
+/// Stores the chats as TOML, an `index.toml` with the order of the chats and a file per chat in `chats/`
+pub struct FileStorage {
+    state_dir: PathBuf,
+    /// Hashes of the files as they were last loaded or written, unchanged files aren't written again
+    saved: HashMap<Uuid, u64>,
+    saved_index: Option<u64>,
+    /// Broken files are only reported, they aren't backed up or moved and the index isn't rewritten
+    read_only: bool,
+}
+
+/// The version of the files that are written, it's increased whenever the format of a chat changes
+pub(super) const VERSION: u32 = 1;
+
+/// `MIGRATIONS[v]` converts a chat of version `v` into the format of version `v + 1`
+const MIGRATIONS: [fn(toml::Value) -> Result<toml::Value>; VERSION as usize] = [migrate_to_message_tree];
+
+/// Version 1: the flat history with nested branches became a message tree, this was also when `state.toml`,
+/// which had no version, was split into the index and a file per chat
+fn migrate_to_message_tree(chat: toml::Value) -> Result<toml::Value> {
+    let mut chat: Chat = chat.try_into()?;
+    chat.migrate_to_message_tree();
+    Ok(toml::Value::try_from(chat)?)
+}
+
+/// The content of `index.toml`
+#[derive(Serialize, Deserialize)]
+struct Index {
+    /// Missing in the files of the first version
+    #[serde(default = "first_version")]
+    version: u32,
+    current_chat_id: Option<Uuid>,
+    /// The ids of the chats in the order of the chat list
+    chats: Vec<Uuid>,
+}
+
+/// The content of a file in `chats/`
+#[derive(Serialize)]
+struct ChatFile<'a> {
+    version: u32,
+    #[serde(flatten)]
+    chat: &'a Chat,
+}
+
+fn first_version() -> u32 {
+    1
+}
+
+impl FileStorage {
+    pub fn new(state_dir: PathBuf) -> Self {
+        FileStorage { state_dir, saved: HashMap::new(), saved_index: None, read_only: false }
+    }
+
+    /// A storage whose [`Storage::load`] doesn't change any files, e.g. to find out whether all chats can be loaded
+    #[cfg(any(feature = "sqlite", test))]
+    pub fn read_only(state_dir: PathBuf) -> Self {
+        FileStorage { read_only: true, ..Self::new(state_dir) }
+    }
+
+    fn chat_file(&self, id: Uuid) -> PathBuf {
+        self.state_dir.join("chats").join(format!("{id}.toml"))
+    }
+
+    /// Loads the chats in the order of `index.toml`, or all chats if the index itself is broken
+    fn load_index(&mut self, index_file: &Path) -> Result<State> {
+        let mut state = State::default();
+        let ids = match read_index(index_file) {
+            Ok(index) => {
+                check_version(index_file, index.version)?;
+                state.current_chat_id = index.current_chat_id;
+                index.chats
+            }
+            Err(err) => {
+                state.problems.push(self.back_up(index_file, err, false));
+                self.chat_ids().unwrap_or_else(|err| {
+                    state.problems.push(format!("Couldn't list the chats: {err:#}"));
+                    Vec::new()
+                })
+            }
+        };
+        let mut dropped = false;
+        for id in ids {
+            let chat_file = self.chat_file(id);
+            if !chat_file.exists() {
+                state.problems.push(format!("{} is missing, the chat was removed from the list", chat_file.display()));
+                dropped = true;
+                continue;
+            }
+            let chat = read_chat(&chat_file);
+            if let Ok(chat) = &chat {
+                check_version(&chat_file, chat.version)?;
+            }
+            match chat.and_then(|chat| Ok((migrate(chat.content, chat.version)?, chat.hash))) {
+                Ok((chat, hash)) => {
+                    // the hash is the one of the file as it was read, so migrated chats are written in the new format
+                    // with the next save, and removed like the others when they're deleted
+                    self.saved.insert(chat.id, hash);
+                    state.chats.push(chat);
+                }
+                // the other chats are still usable, the broken one is moved out of the way, so it isn't loaded again
+                Err(err) => {
+                    state.problems.push(self.back_up(&chat_file, err, true));
+                    dropped = true;
+                }
+            }
+        }
+        // otherwise the next start would look for the same chats again
+        if dropped && !self.read_only {
+            if let Err(err) = self.write_index(&state) {
+                state.problems.push(format!("Couldn't update the list of chats: {err:#}"));
+            }
+        }
+        Ok(state)
+    }
+
+    fn write_index(&mut self, state: &State) -> Result<()> {
+        let index = Index {
+            version: VERSION,
+            current_chat_id: state.current_chat_id,
+            chats: state.chats.iter().map(|c| c.id).collect(),
+        };
+        let content = toml::to_string_pretty(&index)?;
+        let hash = hash(&content);
+        if self.saved_index != Some(hash) {
+            write_atomically(&self.state_dir.join("index.toml"), &content)?;
+            self.saved_index = Some(hash);
+        }
+        Ok(())
+    }
+
+    /// Copies (or moves) a file that couldn't be loaded next to it, so it isn't overwritten, and describes the problem
+    fn back_up(&self, path: &Path, err: anyhow::Error, move_away: bool) -> String {
+        if self.read_only {
+            return format!("Couldn't load {}: {err:#}", path.display());
+        }
+        let mut backup = path.as_os_str().to_owned();
+        backup.push(format!(".broken-{}", chrono::Local::now().format("%Y%m%d-%H%M%S")));
+        let backup = PathBuf::from(backup);
+        let result = if move_away { std::fs::rename(path, &backup) } else { std::fs::copy(path, &backup).map(|_| ()) };
+        match result {
+            Ok(()) => format!("Couldn't load {}, it was backed up to {}: {err:#}", path.display(), backup.display()),
+            Err(backup_err) => {
+                format!("Couldn't load {}, and it couldn't be backed up ({backup_err}): {err:#}", path.display())
+            }
+        }
+    }
+
+    /// The ids of all chat files, the oldest chat first
+    fn chat_ids(&self) -> Result<Vec<Uuid>> {
+        let mut ids = Vec::new();
+        for entry in std::fs::read_dir(self.state_dir.join("chats"))? {
+            let path = entry?.path();
+            let id = path.file_stem().and_then(|s| Uuid::parse_str(&s.to_string_lossy()).ok());
+            if let (Some(id), Some("toml")) = (id, path.extension().and_then(|e| e.to_str())) {
+                let chat = read_chat(&path).ok();
+                // RFC 3339 timestamps are ordered like the time they represent
+                let created_at = chat.and_then(|c| c.content.get("created_at")?.as_str().map(String::from));
+                ids.push((created_at, id));
+            }
+        }
+        ids.sort();
+        Ok(ids.into_iter().map(|(_, id)| id).collect())
+    }
+
+    /// Loads the `state.toml` of the versions before there was a file per chat
+    fn load_state_file(&mut self, state_file: &Path) -> Result<State> {
+        let read = || -> Result<State> {
+            let mut state: toml::Table = toml::from_str(&std::fs::read_to_string(state_file)?)?;
+            let current_chat_id = state.remove("current_chat_id").map(|id| id.try_into()).transpose()?;
+            let chats: Vec<toml::Value> = state.remove("chats").map(|c| c.try_into()).transpose()?.unwrap_or_default();
+            let chats = chats.into_iter().map(|chat| migrate(chat, 0)).collect::<Result<_>>()?;
+            Ok(State { chats, current_chat_id, problems: Vec::new() })
+        };
+        match read() {
+            Ok(state) => Ok(state),
+            Err(err) => Ok(State { problems: vec![self.back_up(state_file, err, false)], ..State::default() }),
+        }
+    }
+}
+
+/// A chat file as it was read, before it's migrated
+struct ChatFileContent {
+    content: toml::Value,
+    version: u32,
+    /// The hash of the whole file
+    hash: u64,
+}
+
+fn read_index(index_file: &Path) -> Result<Index> {
+    Ok(toml::from_str(&std::fs::read_to_string(index_file)?)?)
+}
+
+fn read_chat(path: &Path) -> Result<ChatFileContent> {
+    let file = std::fs::read_to_string(path)?;
+    let mut content: toml::Table = toml::from_str(&file)?;
+    let version = match content.remove("version") {
+        Some(version) => version.try_into()?,
+        None => first_version(),
+    };
+    Ok(ChatFileContent { content: toml::Value::Table(content), version, hash: hash(&file) })
+}
+
+/// Runs the migrations of the chat from `version` to the current version
+pub(super) fn migrate(mut chat: toml::Value, version: u32) -> Result<Chat> {
+    for migration in &MIGRATIONS[version as usize..] {
+        chat = migration(chat)?;
+    }
+    let chat: Chat = chat.try_into()?;
+    chat.check()?;
+    Ok(chat)
+}
+
+/// Files of newer versions are never touched, so nothing is lost when an older version is started by accident
+pub(super) fn check_version(path: &Path, version: u32) -> Result<()> {
+    if version > VERSION {
+        return Err(NewerVersion { path: path.to_path_buf(), kind: "format", version, supported: VERSION }.into());
+    }
+    Ok(())
+}
+
+impl Storage for FileStorage {
+    /// Files that can't be parsed are backed up and reported in [`State::problems`], the other chats are still loaded.
+    /// Chats that are missing or were moved away are removed from `index.toml` right away.
+    /// The `state.toml` of older versions, which contained all chats, is loaded if there's no `index.toml` yet
+    fn load(&mut self) -> Result<State> {
+        let index_file = self.state_dir.join("index.toml");
+        let state_file = self.state_dir.join("state.toml");
+        if index_file.exists() {
+            self.load_index(&index_file)
+        } else if state_file.exists() {
+            self.load_state_file(&state_file)
+        } else {
+            Ok(State::default())
+        }
+    }
+
+    /// Writes the chats that changed, and removes the files of deleted chats
+    fn save(&mut self, state: &State) -> Result<()> {
+        std::fs::create_dir_all(self.state_dir.join("chats"))?;
+
+        for chat in &state.chats {
+            let content = toml::to_string_pretty(&ChatFile { version: VERSION, chat })?;
+            let hash = hash(&content);
+            if self.saved.get(&chat.id) != Some(&hash) {
+                write_atomically(&self.chat_file(chat.id), &content)?;
+                self.saved.insert(chat.id, hash);
+            }
+        }
+
+        self.write_index(state)?;
+
+        // only after the index doesn't refer to them anymore
+        let deleted: Vec<_> =
+            self.saved.keys().filter(|&&id| state.chats.iter().all(|c| c.id != id)).copied().collect();
+        for id in deleted {
+            match std::fs::remove_file(self.chat_file(id)) {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+                _ => self.saved.remove(&id),
+            };
+        }
+        Ok(())
+    }
+}
+
+/// Writes into a temporary file that replaces `path` when it's complete,
+/// so a crash while writing leaves the previous version intact
+pub(super) fn write_atomically(path: &Path, content: &str) -> Result<()> {
+    let tmp = path.with_extension("toml.tmp");
+    let mut f = std::fs::File::create(&tmp).with_context(|| format!("Couldn't create {}", tmp.display()))?;
+    f.write_all(content.as_bytes())?;
+    f.sync_all()?;
+    std::fs::rename(&tmp, path).with_context(|| format!("Couldn't replace {}", path.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chatgpt::types::Role;
+
+    use super::*;
+    use crate::tests::TempDir;
+
+    const CHAT_ID: &str = "6f1b3c2e-8f57-4d0c-9a53-1f0c5a3e7b21";
+    const OTHER_CHAT_ID: &str = "0b0e7c7d-2d7a-4d8e-8d5c-6f3b7f9c1a02";
+
+    fn state_dir() -> TempDir {
+        let dir = TempDir::new();
+        std::fs::create_dir(dir.join("chats")).unwrap();
+        dir
+    }
+
+    fn write_index(dir: &Path, index: &str) {
+        std::fs::write(dir.join("index.toml"), index).unwrap();
+    }
+
+    fn write_chat(dir: &Path, id: &str, chat: &str) {
+        std::fs::write(dir.join("chats").join(format!("{id}.toml")), chat).unwrap();
+    }
+
+    fn chat(id: &str, title: &str) -> String {
+        format!(
+            r#"version = 1
+head = 1
+title = "{title}"
+input = ""
+input_pos = 0
+scroll = 0
+id = "{id}"
+
+[[messages]]
+role = "system"
+content = "You are a test"
+
+[[messages]]
+role = "user"
+content = "Hello"
+parent = 0
+"#
+        )
+    }
+
+    fn index(ids: &[&str]) -> String {
+        let ids: Vec<_> = ids.iter().map(|id| format!("\"{id}\"")).collect();
+        format!("version = 1\ncurrent_chat_id = \"{CHAT_ID}\"\nchats = [{}]\n", ids.join(", "))
+    }
+
+    fn contents(chat: &Chat) -> Vec<(Role, &str, Option<usize>)> {
+        chat.messages.iter().map(|m| (m.role, m.content.as_str(), m.parent)).collect()
+    }
+
+    fn files(dir: &Path) -> Vec<String> {
+        let mut files: Vec<_> = std::fs::read_dir(dir.join("chats"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn migrates_the_state_file_of_the_first_version_into_a_message_tree() {
+        let dir = state_dir();
+        let state_file = format!(
+            r#"current_chat_id = "{CHAT_ID}"
+
+[[chats]]
+title = "Greeting"
+input = "unsent"
+input_pos = 6
+scroll = 0
+id = "{CHAT_ID}"
+
+[[chats.history]]
+role = "system"
+content = "You are a test"
+
+[[chats.history]]
+role = "user"
+content = "Hello"
+
+[[chats.history]]
+role = "assistant"
+content = "Hi"
+"#
+        );
+        std::fs::write(dir.join("state.toml"), state_file).unwrap();
+
+        let state = FileStorage::new(dir.to_path_buf()).load().unwrap();
+        assert!(state.problems.is_empty(), "{:?}", state.problems);
+        assert_eq!(state.current_chat_id, Some(Uuid::parse_str(CHAT_ID).unwrap()));
+        let chat = &state.chats[0];
+        assert_eq!(
+            contents(chat),
+            [(Role::System, "You are a test", None), (Role::User, "Hello", Some(0)), (Role::Assistant, "Hi", Some(1))]
+        );
+        assert_eq!(chat.head, 2);
+        assert_eq!((chat.title.as_str(), chat.input.as_str()), ("Greeting", "unsent"));
+        // it's unknown when they were written
+        assert!(chat.messages.iter().all(|m| m.created_at.is_none()));
+    }
+
+    #[test]
+    fn migrates_the_versions_of_a_flat_history() {
+        let dir = state_dir();
+        // the user message has an earlier answer "A1" as inactive version, the active answer "A2" is in the history
+        let state_file = format!(
+            r#"[[chats]]
+title = "Versions"
+input = ""
+input_pos = 0
+scroll = 0
+id = "{CHAT_ID}"
+
+[[chats.history]]
+role = "system"
+content = "You are a test"
+
+[[chats.history]]
+role = "user"
+content = "Hello"
+branches = [[{{ role = "assistant", content = "A1" }}]]
+active_branch = 1
+
+[[chats.history]]
+role = "assistant"
+content = "A2"
+"#
+        );
+        std::fs::write(dir.join("state.toml"), state_file).unwrap();
+
+        let state = FileStorage::new(dir.to_path_buf()).load().unwrap();
+        assert!(state.problems.is_empty(), "{:?}", state.problems);
+        let chat = &state.chats[0];
+        assert_eq!(
+            contents(chat),
+            [
+                (Role::System, "You are a test", None),
+                (Role::User, "Hello", Some(0)),
+                (Role::Assistant, "A1", Some(1)),
+                (Role::Assistant, "A2", Some(1)),
+            ]
+        );
+        assert_eq!(chat.head, 3);
+    }
+
+    #[test]
+    fn loads_chat_files_without_a_version() {
+        let dir = state_dir();
+        write_index(&dir, &format!("current_chat_id = \"{CHAT_ID}\"\nchats = [\"{CHAT_ID}\"]\n"));
+        write_chat(&dir, CHAT_ID, chat(CHAT_ID, "Unversioned").trim_start_matches("version = 1\n"));
+
+        let mut storage = FileStorage::new(dir.to_path_buf());
+        let state = storage.load().unwrap();
+        assert!(state.problems.is_empty(), "{:?}", state.problems);
+        assert_eq!(state.chats[0].title, "Unversioned");
+        assert_eq!(contents(&state.chats[0]), [(Role::System, "You are a test", None), (Role::User, "Hello", Some(0))]);
+
+        // the file is rewritten with a version when it's saved
+        storage.save(&state).unwrap();
+        let file = std::fs::read_to_string(dir.join("chats").join(format!("{CHAT_ID}.toml"))).unwrap();
+        assert!(file.starts_with("version = 1\n"), "{file}");
+    }
+
+    #[test]
+    fn refuses_files_of_a_newer_version() {
+        let dir = state_dir();
+        write_index(&dir, &index(&[CHAT_ID]));
+        let newer = chat(CHAT_ID, "From the future").replace("version = 1", "version = 2");
+        write_chat(&dir, CHAT_ID, &newer);
+
+        let err = FileStorage::new(dir.to_path_buf()).load().unwrap_err();
+        assert!(err.to_string().contains("written by a newer version"), "{err}");
+        // nothing is moved or rewritten
+        assert_eq!(files(&dir), [format!("{CHAT_ID}.toml")]);
+        assert_eq!(std::fs::read_to_string(dir.join("index.toml")).unwrap(), index(&[CHAT_ID]));
+
+        write_index(&dir, &index(&[CHAT_ID]).replace("version = 1", "version = 2"));
+        assert!(FileStorage::new(dir.to_path_buf()).load().is_err());
+    }
+
+    #[test]
+    fn backs_up_a_broken_chat_and_loads_the_others() {
+        let dir = state_dir();
+        write_index(&dir, &index(&[CHAT_ID, OTHER_CHAT_ID]));
+        write_chat(&dir, CHAT_ID, "title = [");
+        write_chat(&dir, OTHER_CHAT_ID, &chat(OTHER_CHAT_ID, "Intact"));
+
+        let state = FileStorage::new(dir.to_path_buf()).load().unwrap();
+        assert_eq!(state.chats.len(), 1);
+        assert_eq!(state.chats[0].title, "Intact");
+        assert_eq!(state.problems.len(), 1);
+        assert!(state.problems[0].contains("it was backed up to"), "{}", state.problems[0]);
+        let files = files(&dir);
+        assert_eq!(files[0], format!("{OTHER_CHAT_ID}.toml"));
+        assert!(files[1].starts_with(&format!("{CHAT_ID}.toml.broken-")), "{files:?}");
+
+        // the broken chat isn't looked for again
+        let state = FileStorage::new(dir.to_path_buf()).load().unwrap();
+        assert!(state.problems.is_empty(), "{:?}", state.problems);
+        assert_eq!(state.chats.len(), 1);
+    }
+
+    #[test]
+    fn backs_up_a_chat_without_messages() {
+        let dir = state_dir();
+        write_index(&dir, &index(&[CHAT_ID]));
+        let empty = chat(CHAT_ID, "Empty");
+        write_chat(&dir, CHAT_ID, &empty[..empty.find("[[messages]]").unwrap()]);
+
+        let state = FileStorage::new(dir.to_path_buf()).load().unwrap();
+        assert!(state.chats.is_empty());
+        assert!(state.problems[0].contains("The chat has no messages"), "{}", state.problems[0]);
+        assert!(files(&dir)[0].starts_with(&format!("{CHAT_ID}.toml.broken-")));
+    }
+
+    #[test]
+    fn reports_a_missing_chat_and_removes_it_from_the_index() {
+        let dir = state_dir();
+        write_index(&dir, &index(&[CHAT_ID, OTHER_CHAT_ID]));
+        write_chat(&dir, OTHER_CHAT_ID, &chat(OTHER_CHAT_ID, "Intact"));
+
+        let state = FileStorage::new(dir.to_path_buf()).load().unwrap();
+        assert_eq!(state.chats.len(), 1);
+        assert!(state.problems[0].contains("is missing"), "{}", state.problems[0]);
+        let index = read_index(&dir.join("index.toml")).unwrap();
+        assert_eq!(index.chats, [Uuid::parse_str(OTHER_CHAT_ID).unwrap()]);
+    }
+
+    #[test]
+    fn read_only_storage_doesnt_touch_broken_files() {
+        let dir = state_dir();
+        write_index(&dir, &index(&[CHAT_ID, OTHER_CHAT_ID]));
+        write_chat(&dir, CHAT_ID, "title = [");
+
+        let state = FileStorage::read_only(dir.to_path_buf()).load().unwrap();
+        assert_eq!(state.problems.len(), 2);
+        assert_eq!(files(&dir), [format!("{CHAT_ID}.toml")]);
+        assert_eq!(std::fs::read_to_string(dir.join("index.toml")).unwrap(), index(&[CHAT_ID, OTHER_CHAT_ID]));
+    }
+}
@@ -0,0 +1,399 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use uuid::Uuid;
+
+use super::{hash, FileStorage, NewerVersion, Storage};
+use crate::chat::{Chat, Message, State};
+
+/// The version of the schema, it's stored as the `user_version` of the database
+const VERSION: u32 = 2;
+
+const SCHEMA: &str = "
+    CREATE TABLE chats (
+        id TEXT PRIMARY KEY,
+        -- the order of the chat list
+        position INTEGER NOT NULL,
+        title TEXT NOT NULL,
+        -- the other fields of the chat as JSON
+        data TEXT NOT NULL
+    );
+    CREATE TABLE messages (
+        chat_id TEXT NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+        -- the index in the message tree of the chat
+        idx INTEGER NOT NULL,
+        parent INTEGER,
+        role TEXT NOT NULL,
+        content TEXT NOT NULL,
+        stopped INTEGER NOT NULL,
+        created_at TEXT,
+        model TEXT,
+        finish_reason TEXT,
+        prompt_tokens INTEGER,
+        completion_tokens INTEGER,
+        time_to_first_token_ms INTEGER,
+        duration_ms INTEGER,
+        PRIMARY KEY (chat_id, idx)
+    );
+    CREATE TABLE settings (
+        key TEXT PRIMARY KEY,
+        value TEXT
+    );
+";
+
+/// `MIGRATIONS[v - 1]` converts the schema of version `v` into version `v + 1`, the first version is [`SCHEMA`]
+const MIGRATIONS: [&str; VERSION as usize - 1] =
+    ["ALTER TABLE messages ADD COLUMN incomplete INTEGER NOT NULL DEFAULT 0;"];
+
+/// Stores the chats in `chats.sqlite`, with a row per chat and a row per message,
+/// so a new answer only inserts a row instead of writing the whole chat again
+pub struct SqliteStorage {
+    connection: Connection,
+    /// Chats that can't be loaded are backed up here
+    state_dir: PathBuf,
+    /// Hashes of the rows as they were last loaded or written, unchanged rows aren't written again
+    saved: HashMap<Uuid, SavedChat>,
+}
+
+struct SavedChat {
+    chat: u64,
+    messages: Vec<u64>,
+}
+
+impl SqliteStorage {
+    /// Opens the database in `state_dir`, a new database is filled with the chats of the [`FileStorage`] in the same directory
+    pub fn open(state_dir: &Path) -> Result<Self> {
+        std::fs::create_dir_all(state_dir)?;
+        let path = state_dir.join("chats.sqlite");
+        let connection = Connection::open(&path).with_context(|| format!("Couldn't open {}", path.display()))?;
+        connection.pragma_update(None, "foreign_keys", true)?;
+        let mut storage = SqliteStorage { connection, state_dir: state_dir.to_path_buf(), saved: HashMap::new() };
+
+        let version: u32 = storage.connection.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        if version > VERSION {
+            return Err(NewerVersion { path, kind: "schema", version, supported: VERSION }.into());
+        }
+        if version < VERSION {
+            let tx = storage.connection.transaction()?;
+            if version == 0 {
+                tx.execute_batch(SCHEMA)?;
+            }
+            for migration in &MIGRATIONS[version.max(1) as usize - 1..] {
+                tx.execute_batch(migration)?;
+            }
+            if version == 0 {
+                // read only, so the files stay as they are when the copy is refused
+                let state = FileStorage::read_only(state_dir.to_path_buf()).load()?;
+                // the chats that couldn't be loaded would be missing from the database for good
+                if !state.problems.is_empty() {
+                    bail!(
+                        "The chats can't be copied into {}, fix or remove these files or keep `storage = \"files\"`:\n{}",
+                        path.display(),
+                        state.problems.join("\n")
+                    );
+                }
+                write(&tx, &mut storage.saved, &state)?;
+            }
+            tx.pragma_update(None, "user_version", VERSION)?;
+            tx.commit()?;
+        }
+        Ok(storage)
+    }
+
+    fn load_chat(&self, id: &str, data: &str) -> Result<Chat> {
+        let mut chat: Chat = serde_json::from_str(data)?;
+        let mut messages = self.connection.prepare_cached(
+            "SELECT parent, role, content, stopped, created_at, model, finish_reason, prompt_tokens, completion_tokens,
+                time_to_first_token_ms, duration_ms, incomplete
+            FROM messages WHERE chat_id = ? ORDER BY idx",
+        )?;
+        let rows = messages.query_map([id], |row| {
+            let role: String = row.get(1)?;
+            // an unknown role makes the chat broken, instead of sending the message as something else
+            let role = serde_json::from_value(serde_json::Value::String(role))
+                .map_err(|err| rusqlite::Error::FromSqlConversionFailure(1, rusqlite::types::Type::Text, err.into()))?;
+            let mut message = Message::new(role, row.get(2)?, row.get(0)?);
+            message.stopped = row.get(3)?;
+            message.created_at = row.get(4)?;
+            message.model = row.get(5)?;
+            message.finish_reason = row.get(6)?;
+            message.prompt_tokens = row.get(7)?;
+            message.completion_tokens = row.get(8)?;
+            message.time_to_first_token_ms = row.get(9)?;
+            message.duration_ms = row.get(10)?;
+            message.incomplete = row.get(11)?;
+            Ok(message)
+        })?;
+        chat.messages = rows.collect::<rusqlite::Result<_>>()?;
+        chat.check()?;
+        Ok(chat)
+    }
+
+    /// Moves a chat that couldn't be loaded with its messages from the database into a JSON file, and describes the problem
+    fn back_up(&self, id: &str, data: &str, err: anyhow::Error) -> String {
+        let path =
+            self.state_dir.join(format!("chat-{id}.json.broken-{}", chrono::Local::now().format("%Y%m%d-%H%M%S")));
+        let back_up = || -> Result<()> {
+            let messages: String = self.connection.query_row(
+                "SELECT json_group_array(json_object('idx', idx, 'parent', parent, 'role', role, 'content', content,
+                    'stopped', stopped, 'created_at', created_at, 'model', model, 'finish_reason', finish_reason,
+                    'prompt_tokens', prompt_tokens, 'completion_tokens', completion_tokens,
+                    'time_to_first_token_ms', time_to_first_token_ms, 'duration_ms', duration_ms,
+                    'incomplete', incomplete))
+                FROM messages WHERE chat_id = ?",
+                [id],
+                |row| row.get(0),
+            )?;
+            let messages: serde_json::Value = serde_json::from_str(&messages)?;
+            let backup = serde_json::json!({ "id": id, "data": data, "messages": messages });
+            std::fs::write(&path, serde_json::to_string_pretty(&backup)?)?;
+            // its messages are deleted with it
+            self.connection.execute("DELETE FROM chats WHERE id = ?", [id])?;
+            Ok(())
+        };
+        match back_up() {
+            Ok(()) => format!("Couldn't load the chat {id}, it was moved to {}: {err:#}", path.display()),
+            Err(backup_err) => {
+                format!("Couldn't load the chat {id}, and it couldn't be backed up ({backup_err:#}): {err:#}")
+            }
+        }
+    }
+}
+
+impl Storage for SqliteStorage {
+    /// Chats that can't be loaded are backed up and reported in [`State::problems`], the other chats are still loaded
+    fn load(&mut self) -> Result<State> {
+        let mut state = State::default();
+        let chats: Vec<(String, String)> = self
+            .connection
+            .prepare("SELECT id, data FROM chats ORDER BY position")?
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<_>>()?;
+        for (id, data) in chats {
+            match self.load_chat(&id, &data) {
+                Ok(chat) => state.chats.push(chat),
+                // the other chats are still usable, the broken one is moved out of the database, so it isn't loaded again
+                Err(err) => state.problems.push(self.back_up(&id, &data, err)),
+            }
+        }
+        let current_chat_id: Option<String> = self
+            .connection
+            .query_row("SELECT value FROM settings WHERE key = 'current_chat_id'", [], |row| row.get(0))
+            .optional()?
+            .flatten();
+        state.current_chat_id = current_chat_id.and_then(|id| Uuid::parse_str(&id).ok());
+
+        self.saved =
+            state.chats.iter().enumerate().map(|(position, chat)| (chat.id, saved_chat(position, chat))).collect();
+        Ok(state)
+    }
+
+    /// Writes the chats and messages that changed in one transaction
+    fn save(&mut self, state: &State) -> Result<()> {
+        let tx = self.connection.transaction()?;
+        write(&tx, &mut self.saved, state)?;
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+/// The chat without its messages, they have their own table
+fn chat_data(chat: &Chat) -> String {
+    let mut data = serde_json::to_value(chat).unwrap_or_default();
+    if let Some(data) = data.as_object_mut() {
+        data.remove("messages");
+    }
+    data.to_string()
+}
+
+fn saved_chat(position: usize, chat: &Chat) -> SavedChat {
+    SavedChat {
+        chat: hash(&format!("{position} {}", chat_data(chat))),
+        messages: chat.messages.iter().map(|m| hash(&serde_json::to_string(m).unwrap_or_default())).collect(),
+    }
+}
+
+fn write(tx: &Transaction, saved: &mut HashMap<Uuid, SavedChat>, state: &State) -> Result<()> {
+    let mut previously_saved = std::mem::take(saved);
+    for (position, chat) in state.chats.iter().enumerate() {
+        let id = chat.id.to_string();
+        let new = saved_chat(position, chat);
+        let old = previously_saved.remove(&chat.id);
+        if old.as_ref().map(|old| old.chat) != Some(new.chat) {
+            tx.execute(
+                "INSERT INTO chats (id, position, title, data) VALUES (?1, ?2, ?3, ?4)
+                ON CONFLICT (id) DO UPDATE SET position = ?2, title = ?3, data = ?4",
+                params![id, position, chat.title, chat_data(chat)],
+            )?;
+        }
+
+        let old_messages = old.map(|old| old.messages).unwrap_or_default();
+        for (idx, message) in chat.messages.iter().enumerate() {
+            if old_messages.get(idx) == Some(&new.messages[idx]) {
+                continue;
+            }
+            let role = serde_json::to_value(message.role)?;
+            tx.execute(
+                "INSERT OR REPLACE INTO messages (chat_id, idx, parent, role, content, stopped, created_at, model,
+                    finish_reason, prompt_tokens, completion_tokens, time_to_first_token_ms, duration_ms, incomplete)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+                params![
+                    id,
+                    idx,
+                    message.parent,
+                    role.as_str(),
+                    message.content,
+                    message.stopped,
+                    message.created_at,
+                    message.model,
+                    message.finish_reason,
+                    message.prompt_tokens,
+                    message.completion_tokens,
+                    message.time_to_first_token_ms,
+                    message.duration_ms,
+                    message.incomplete,
+                ],
+            )?;
+        }
+        // e.g. the partial answer of a failed request was discarded
+        if old_messages.len() > chat.messages.len() {
+            tx.execute("DELETE FROM messages WHERE chat_id = ? AND idx >= ?", params![id, chat.messages.len()])?;
+        }
+        saved.insert(chat.id, new);
+    }
+
+    // what's left are the chats that were deleted, their messages are deleted with them
+    for id in previously_saved.keys() {
+        tx.execute("DELETE FROM chats WHERE id = ?", [id.to_string()])?;
+    }
+
+    tx.execute(
+        "INSERT OR REPLACE INTO settings (key, value) VALUES ('current_chat_id', ?)",
+        [state.current_chat_id.map(|id| id.to_string())],
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::TempDir;
+
+    fn state(titles: &[&str]) -> State {
+        let chats: Vec<_> = titles
+            .iter()
+            .map(|title| {
+                let mut chat = Chat::new("You are a test".into());
+                chat.title = title.to_string();
+                chat.push(chatgpt::types::Role::User, format!("Hello from {title}"));
+                chat
+            })
+            .collect();
+        State { current_chat_id: chats.first().map(|c| c.id), chats, problems: Vec::new() }
+    }
+
+    fn titles(state: &State) -> Vec<&str> {
+        state.chats.iter().map(|c| c.title.as_str()).collect()
+    }
+
+    #[test]
+    fn copies_the_chat_files_into_a_new_database() {
+        let dir = TempDir::new();
+        FileStorage::new(dir.to_path_buf()).save(&state(&["First", "Second"])).unwrap();
+
+        let state = SqliteStorage::open(&dir).unwrap().load().unwrap();
+        assert!(state.problems.is_empty(), "{:?}", state.problems);
+        assert_eq!(titles(&state), ["First", "Second"]);
+        assert_eq!(state.chats[1].messages[1].content, "Hello from Second");
+        assert_eq!(state.current_chat_id, Some(state.chats[0].id));
+    }
+
+    #[test]
+    fn doesnt_create_the_database_if_a_chat_file_is_broken() {
+        let dir = TempDir::new();
+        let state = state(&["First"]);
+        FileStorage::new(dir.to_path_buf()).save(&state).unwrap();
+        let chat_file = dir.join("chats").join(format!("{}.toml", state.chats[0].id));
+        std::fs::write(&chat_file, "title = [").unwrap();
+
+        assert!(SqliteStorage::open(&dir).is_err());
+        // the broken file stays where it is, so the next try finds it again
+        assert!(chat_file.exists());
+        assert!(SqliteStorage::open(&dir).is_err());
+    }
+
+    #[test]
+    fn migrates_the_first_schema() {
+        let dir = TempDir::new();
+        let connection = Connection::open(dir.join("chats.sqlite")).unwrap();
+        connection.execute_batch(SCHEMA).unwrap();
+        connection.pragma_update(None, "user_version", 1).unwrap();
+        drop(connection);
+
+        let mut storage = SqliteStorage::open(&dir).unwrap();
+        let mut state = state(&["First"]);
+        state.chats[0].messages[1].incomplete = true;
+        storage.save(&state).unwrap();
+        let state = SqliteStorage::open(&dir).unwrap().load().unwrap();
+        assert!(state.chats[0].messages[1].incomplete);
+    }
+
+    #[test]
+    fn refuses_a_database_of_a_newer_version() {
+        let dir = TempDir::new();
+        let connection = Connection::open(dir.join("chats.sqlite")).unwrap();
+        connection.pragma_update(None, "user_version", VERSION + 1).unwrap();
+        drop(connection);
+
+        let err = SqliteStorage::open(&dir).err().unwrap();
+        assert!(err.to_string().contains("written by a newer version"), "{err}");
+    }
+
+    #[test]
+    fn backs_up_a_broken_chat_and_loads_the_others() {
+        let dir = TempDir::new();
+        let mut storage = SqliteStorage::open(&dir).unwrap();
+        let state = state(&["Broken", "Intact"]);
+        storage.save(&state).unwrap();
+        let broken_id = state.chats[0].id.to_string();
+        storage.connection.execute("UPDATE chats SET data = '{' WHERE id = ?", [&broken_id]).unwrap();
+
+        let state = storage.load().unwrap();
+        assert_eq!(titles(&state), ["Intact"]);
+        assert!(state.problems[0].contains("it was moved to"), "{}", state.problems[0]);
+        let backups: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.to_string_lossy().contains(".json.broken-"))
+            .collect();
+        let backup: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&backups[0]).unwrap()).unwrap();
+        assert_eq!(backup["data"], "{");
+        assert_eq!(backup["messages"][1]["content"], "Hello from Broken");
+
+        let state = SqliteStorage::open(&dir).unwrap().load().unwrap();
+        assert!(state.problems.is_empty(), "{:?}", state.problems);
+        assert_eq!(titles(&state), ["Intact"]);
+    }
+
+    #[test]
+    fn backs_up_a_chat_with_an_unknown_role() {
+        let dir = TempDir::new();
+        let mut storage = SqliteStorage::open(&dir).unwrap();
+        let state = state(&["Broken", "Intact"]);
+        storage.save(&state).unwrap();
+        let broken_id = state.chats[0].id.to_string();
+        storage
+            .connection
+            .execute("UPDATE messages SET role = 'robot' WHERE chat_id = ? AND idx = 1", [&broken_id])
+            .unwrap();
+
+        let state = storage.load().unwrap();
+        assert_eq!(titles(&state), ["Intact"]);
+        assert!(state.problems[0].contains("robot"), "{}", state.problems[0]);
+        assert!(state.problems[0].contains("it was moved to"), "{}", state.problems[0]);
+    }
+}
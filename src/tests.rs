@@ -0,0 +1,381 @@
+//! Runs the app loop on a [`TestBackend`] and the chat requests on the [`MockBackend`],
+//! the keys are sent as if they came from the terminal
+
+use std::{
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use crossterm::event::KeyEvent;
+use ratatui::backend::TestBackend;
+use tokio::task::JoinHandle;
+
+use super::*;
+use crate::{backend::MockBackend, config::MockSettings};
+
+/// A new directory in the temp directory of the system, it's removed with everything in it when it's dropped,
+/// so also when a test fails
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new() -> Self {
+        let dir = std::env::temp_dir().join(format!("chatgpt-tui-test-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+}
+
+impl std::ops::Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        std::fs::remove_dir_all(&self.0).ok();
+    }
+}
+
+/// Sends the chats, as they would be written, to the test whenever they're saved
+struct TestStorage(mpsc::UnboundedSender<Vec<Chat>>);
+
+impl Storage for TestStorage {
+    fn load(&mut self) -> Result<State> {
+        Ok(State::default())
+    }
+
+    fn save(&mut self, state: &State) -> Result<()> {
+        self.0.send(serde_json::from_value(serde_json::to_value(&state.chats)?)?).ok();
+        Ok(())
+    }
+}
+
+/// Fails the first `failures` requests, and every title request if `fail_titles` is set, after a partial answer,
+/// the other requests are answered by the [`MockBackend`]
+struct FlakyBackend {
+    failures: AtomicUsize,
+    fail_titles: bool,
+    mock: MockBackend,
+}
+
+impl FlakyBackend {
+    fn new(failures: usize, fail_titles: bool) -> Box<Self> {
+        Box::new(FlakyBackend {
+            failures: AtomicUsize::new(failures),
+            fail_titles,
+            mock: MockBackend::new(mock_settings()),
+        })
+    }
+}
+
+impl ChatBackend for FlakyBackend {
+    fn send_history_streaming(
+        &self,
+        messages: Vec<ChatMessage>,
+        model: &ModelSettings,
+    ) -> BoxFuture<'static, Result<ResponseStream>> {
+        // title requests only consist of a system message
+        let title = messages.len() == 1 && messages[0].role == Role::System;
+        if !(title && self.fail_titles)
+            && self.failures.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |f| f.checked_sub(1)).is_err()
+        {
+            return self.mock.send_history_streaming(messages, model);
+        }
+        let chunks = vec![
+            Ok(ResponseChunk::BeginResponse { role: Role::Assistant }),
+            Ok(ResponseChunk::Content { delta: "partial ".into() }),
+            Err(anyhow::anyhow!("Stream closed abruptly")),
+        ];
+        async move { Ok(futures::stream::iter(chunks).boxed()) }.boxed()
+    }
+}
+
+fn mock_settings() -> MockSettings {
+    MockSettings { responses: Vec::new(), first_chunk_delay_ms: 0, chunk_delay_ms: 0 }
+}
+
+struct TestApp {
+    app_message_sender: mpsc::UnboundedSender<AppMessage>,
+    saves: mpsc::UnboundedReceiver<Vec<Chat>>,
+    app: JoinHandle<Result<(App, Terminal<TestBackend>)>>,
+}
+
+impl TestApp {
+    /// Starts the app in a new chat, like `main` does
+    fn start(backend: Box<dyn ChatBackend>) -> Self {
+        Self::start_with(backend, State::default())
+    }
+
+    /// Starts the app with the current chat of `state` opened, or a new chat if there's none
+    fn start_with(backend: Box<dyn ChatBackend>, state: State) -> Self {
+        let (app_message_sender, app_message_receiver) = mpsc::unbounded_channel();
+        let (chatgpt_message_sender, chatgpt_message_receiver) = mpsc::unbounded_channel();
+        let (quit_signal_sender, quit_signal_receiver) = watch::channel(());
+        let (save_sender, saves) = mpsc::unbounded_channel();
+        let model = ModelSettings::default();
+
+        let mut app = App::new(
+            Config::default(),
+            model.clone(),
+            state,
+            Box::new(TestStorage(save_sender)),
+            app_message_receiver,
+            chatgpt_message_sender,
+            quit_signal_sender,
+        );
+        if app.state.current_chat_id.is_none() {
+            app.new_chat();
+        }
+        app.ui_mode = UiMode::Chat;
+
+        tokio::spawn(handle_chatgpt(
+            backend,
+            model,
+            app_message_sender.clone(),
+            chatgpt_message_receiver,
+            quit_signal_receiver,
+        ));
+        let app = tokio::spawn(async move {
+            let mut terminal = Terminal::new(TestBackend::new(100, 30))?;
+            // termimad can only draw on a real terminal
+            app_loop(&mut terminal, &mut app, |_, app| {
+                app.draw_chat_area = None;
+                Ok(())
+            })
+            .await?;
+            Ok((app, terminal))
+        });
+        TestApp { app_message_sender, saves, app }
+    }
+
+    fn key(&self, code: KeyCode, modifiers: KeyModifiers) {
+        self.app_message_sender.send(AppMessage::KeyEvent(KeyEvent::new(code, modifiers))).unwrap();
+    }
+
+    fn ctrl(&self, c: char) {
+        self.key(KeyCode::Char(c), KeyModifiers::CONTROL);
+    }
+
+    fn send_message(&self, text: &str) {
+        for c in text.chars() {
+            self.key(KeyCode::Char(c), KeyModifiers::NONE);
+        }
+        self.key(KeyCode::Enter, KeyModifiers::NONE);
+    }
+
+    /// Waits until the chats are saved in a state that `until` accepts
+    async fn saved(&mut self, until: impl Fn(&[Chat]) -> bool) -> Vec<Chat> {
+        let saved = async {
+            loop {
+                let chats = self.saves.recv().await.expect("the app stopped");
+                if until(&chats) {
+                    return chats;
+                }
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), saved).await.expect("the chats weren't saved in time")
+    }
+
+    /// Goes back to the chat list and quits, the terminal shows the chat list
+    async fn quit(self) -> (App, Terminal<TestBackend>) {
+        self.key(KeyCode::Esc, KeyModifiers::NONE);
+        self.key(KeyCode::Char('q'), KeyModifiers::NONE);
+        self.app.await.unwrap().unwrap()
+    }
+}
+
+fn screen(terminal: &Terminal<TestBackend>) -> String {
+    let buffer = terminal.backend().buffer();
+    let lines = buffer.content.chunks(buffer.area.width as usize);
+    lines.map(|line| line.iter().map(|cell| cell.symbol.as_str()).collect::<String>()).collect::<Vec<_>>().join("\n")
+}
+
+fn answered(chats: &[Chat]) -> bool {
+    chats[0].messages.last().map_or(false, |m| m.role == Role::Assistant && !m.incomplete)
+}
+
+#[tokio::test]
+async fn handle_chatgpt_streams_the_answer_of_the_backend() {
+    let (app_message_sender, mut app_message_receiver) = mpsc::unbounded_channel();
+    let (chatgpt_message_sender, chatgpt_message_receiver) = mpsc::unbounded_channel();
+    let (_quit_signal_sender, quit_signal_receiver) = watch::channel(());
+    let backend = Box::new(MockBackend::new(mock_settings()));
+    let model = ModelSettings::default();
+    tokio::spawn(handle_chatgpt(
+        backend,
+        model.clone(),
+        app_message_sender,
+        chatgpt_message_receiver,
+        quit_signal_receiver,
+    ));
+
+    let id = Uuid::new_v4();
+    let messages = vec![ChatMessage { role: Role::User, content: "Hello there".into() }];
+    chatgpt_message_sender.send(ChatGPTMessage::ChatRequest { id, message_id: 1, messages, model }).unwrap();
+
+    let mut chunks = Vec::new();
+    while let Some(AppMessage::ChatGPTMessageChunkReceived { message_type, chunk }) = app_message_receiver.recv().await
+    {
+        assert!(matches!(message_type, ChatGPTMessageChunkType::Chat { chat_id, message_id: 1 } if chat_id == id));
+        chunks.push(chunk);
+        if chunks.last() == Some(&ResponseChunk::Done) {
+            break;
+        }
+    }
+    assert_eq!(
+        chunks,
+        [
+            ResponseChunk::BeginResponse { role: Role::Assistant },
+            ResponseChunk::Content { delta: "You ".into() },
+            ResponseChunk::Content { delta: "said: ".into() },
+            ResponseChunk::Content { delta: "Hello ".into() },
+            ResponseChunk::Content { delta: "there".into() },
+            ResponseChunk::CloseResponse { finish_reason: Some("stop".into()) },
+            ResponseChunk::Usage { prompt_tokens: 2, completion_tokens: 4 },
+            ResponseChunk::Done,
+        ]
+    );
+}
+
+#[tokio::test]
+async fn handle_chatgpt_stops_a_cancelled_request() {
+    let (app_message_sender, mut app_message_receiver) = mpsc::unbounded_channel();
+    let (chatgpt_message_sender, chatgpt_message_receiver) = mpsc::unbounded_channel();
+    let (_quit_signal_sender, quit_signal_receiver) = watch::channel(());
+    let settings = MockSettings { chunk_delay_ms: 50, ..mock_settings() };
+    let model = ModelSettings::default();
+    let backend = Box::new(MockBackend::new(settings));
+    tokio::spawn(handle_chatgpt(
+        backend,
+        model.clone(),
+        app_message_sender,
+        chatgpt_message_receiver,
+        quit_signal_receiver,
+    ));
+
+    let id = Uuid::new_v4();
+    let messages = vec![ChatMessage { role: Role::User, content: "one two three four five six".into() }];
+    chatgpt_message_sender.send(ChatGPTMessage::ChatRequest { id, message_id: 1, messages, model }).unwrap();
+    // the first chunk is sent right away
+    let first = app_message_receiver.recv().await.unwrap();
+    assert!(matches!(
+        first,
+        AppMessage::ChatGPTMessageChunkReceived { chunk: ResponseChunk::BeginResponse { .. }, .. }
+    ));
+    chatgpt_message_sender.send(ChatGPTMessage::CancelRequest { id }).unwrap();
+
+    let rest = tokio::time::timeout(Duration::from_millis(500), app_message_receiver.recv()).await;
+    assert!(rest.is_err(), "received a chunk after the request was cancelled: {rest:?}");
+}
+
+#[tokio::test]
+async fn answers_and_titles_a_new_chat() {
+    let mut app = TestApp::start(Box::new(MockBackend::new(mock_settings())));
+    app.send_message("Hello");
+
+    let chats = app.saved(answered).await;
+    let answer = &chats[0].messages[2];
+    assert_eq!(answer.content, "You said: Hello");
+    assert_eq!(answer.model.as_deref(), Some(ModelSettings::default().engine.as_str()));
+    assert_eq!(answer.finish_reason.as_deref(), Some("stop"));
+    assert_eq!(answer.completion_tokens, Some(3));
+    assert!(answer.duration_ms.is_some());
+
+    // title requests only consist of a system message
+    app.saved(|chats| chats[0].title == "Mock response").await;
+    let (app, terminal) = app.quit().await;
+    assert_eq!(app.state.chats.len(), 1);
+    assert!(screen(&terminal).contains("Mock response"), "{}", screen(&terminal));
+}
+
+#[tokio::test]
+async fn a_failed_title_keeps_the_previous_title() {
+    let mut app = TestApp::start(FlakyBackend::new(0, true));
+    app.send_message("Hello");
+    let chats = app.saved(answered).await;
+    // the title is requested after the first answer, it has failed by the time the second answer is complete
+    app.send_message("Again");
+    app.saved(|chats| chats[0].messages.len() == 5 && answered(chats)).await;
+
+    let (app, _) = app.quit().await;
+    let chat = &app.state.chats[0];
+    assert_eq!(chat.title, chats[0].title);
+    assert!(chat.generated_title.is_none());
+}
+
+#[tokio::test]
+async fn retrying_a_failed_answer_drops_the_partial_answer() {
+    let mut app = TestApp::start(FlakyBackend::new(1, false));
+    app.send_message("Hello");
+
+    let chats = app.saved(|chats| chats[0].messages.len() == 3).await;
+    assert_eq!(chats[0].messages[2].content, "partial ");
+    app.ctrl('r');
+
+    let chats = app.saved(answered).await;
+    let contents: Vec<_> = chats[0].messages.iter().skip(1).map(|m| m.content.as_str()).collect();
+    assert_eq!(contents, ["Hello", "You said: Hello"]);
+    let (app, _) = app.quit().await;
+    assert!(app.state.chats[0].error.is_none());
+}
+
+#[tokio::test]
+async fn retrying_a_failed_continue_keeps_the_answer() {
+    let mut chat = Chat::new("You are a test".into());
+    chat.push(Role::User, "Hello".into());
+    chat.push(Role::Assistant, "You said".into());
+    chat.messages[2].incomplete = true;
+    let state = State { current_chat_id: Some(chat.id), chats: vec![chat], problems: Vec::new() };
+    let mut app = TestApp::start_with(FlakyBackend::new(1, false), state);
+
+    app.ctrl('o');
+    let chats = app.saved(|chats| chats[0].messages[2].content == "You saidpartial ").await;
+    assert_eq!(chats[0].messages.len(), 3);
+    app.ctrl('r');
+
+    let chats = app.saved(answered).await;
+    assert_eq!(chats[0].messages.len(), 3);
+    assert_eq!(chats[0].messages[2].content, format!("You saidpartial You said: {CONTINUE_PROMPT}"));
+    app.quit().await;
+}
+
+#[tokio::test]
+async fn deleting_a_chat_selects_a_listed_chat() {
+    // listed newest first: D, C, B, A
+    let chats: Vec<_> = ["A", "Archived", "B", "C", "D"]
+        .into_iter()
+        .enumerate()
+        .map(|(i, title)| {
+            let mut chat = Chat::new(format!("You are test {i}"));
+            chat.title = title.into();
+            chat.archived = title == "Archived";
+            chat
+        })
+        .collect();
+    let (deleted, expected) = (chats[0].id, chats[2].id);
+    let state = State { current_chat_id: Some(chats[4].id), chats, problems: Vec::new() };
+    let mut app = TestApp::start_with(Box::new(MockBackend::new(mock_settings())), state);
+    app.key(KeyCode::Esc, KeyModifiers::NONE);
+    app.key(KeyCode::Down, KeyModifiers::NONE);
+    // A is opened from the search, so the row of C is still remembered
+    app.key(KeyCode::Char('/'), KeyModifiers::NONE);
+    app.send_message("test 0");
+    app.key(KeyCode::Esc, KeyModifiers::NONE);
+    app.key(KeyCode::Char('d'), KeyModifiers::NONE);
+    app.key(KeyCode::Char('y'), KeyModifiers::NONE);
+
+    let chats = app.saved(|chats| chats.len() == 4).await;
+    assert!(chats.iter().all(|c| c.id != deleted));
+    let (app, _) = app.quit().await;
+    assert_eq!(app.state.current_chat_id, Some(expected));
+}